@@ -1,6 +1,6 @@
 #![no_std]
 #![no_main]
-#![cfg(all(feature = "stm32l4x2", feature = "extra-traits"))]
+#![cfg(feature = "extra-traits")]
 
 // extern crate panic_halt;
 extern crate panic_semihosting;
@@ -24,11 +24,12 @@ fn main() -> ! {
     // hprintln!("clocks = {:?}", clocks).unwrap();
 
     // let's go!
-    let mut flash = hal::flash::Flash::new(dp.FLASH);
-    // flash.unlock();  // not pulled in scope by prelude
+    let flash = hal::flash::Flash::new(dp.FLASH);
 
     let boot_bits = flash.get_boot_bits();
     hprintln!("boot_bits = {:?}", boot_bits).unwrap();
+    let geometry = flash.geometry();
+    hprintln!("geometry = {:?}", geometry).unwrap();
 
     let mut rng = hal::rng::Rng::new(dp.RNG, clocks);
     const TEST_SIZE: usize = 63*8; // 7*72;
@@ -38,9 +39,13 @@ fn main() -> ! {
         .expect("could not get entropy from RNG peripheral");
 
     let page = 100usize;
-    let faddr = 0x800_0000 + page*2048;
-    {
-        let mut unlocked_flash = flash.unlocked();
+    let faddr = geometry.page_address(hal::flash::Bank::Bank1, page as u8);
+    let flash = {
+        // a failed unlock gives back the locked flash along with the error
+        let mut unlocked_flash = flash
+            .unlock()
+            .map_err(|(_flash, error)| error)
+            .expect("could not unlock flash");
 
         unlocked_flash
             .erase_page(page as u8)
@@ -52,10 +57,10 @@ fn main() -> ! {
             .write(faddr, &random_test_data)
             .expect("could not write to flash address");
 
-        // if UnlockGuard is not enclosed in a block like here,
-        // can also explicitly drop it to get back access to normal flash
-        // drop(unlocked_flash);
-    }
+        // dropping `unlocked_flash` would lock the flash as well,
+        // but also give up the peripheral
+        unlocked_flash.lock()
+    };
 
     let mut buf = [0u8; TEST_SIZE];
     // flash.read_native(faddr, &mut buf);
@@ -3,9 +3,20 @@ MEMORY
   /* NOTE K = KiBi = 1024 bytes */
   /* TODO Adjust these memory regions to match your device memory layout */
   FLASH : ORIGIN = 0x8000000, LENGTH = 256K 
-  RAM : ORIGIN = 0x20000000, LENGTH = 64K
+  /* SRAM1 only, SRAM2 is accessed at its own address */
+  RAM : ORIGIN = 0x20000000, LENGTH = 48K
+  SRAM2 : ORIGIN = 0x10000000, LENGTH = 16K
 }
 
+/* Statics placed in SRAM2 by `sram2!`, not initialized at startup */
+SECTIONS
+{
+  .sram2 (NOLOAD) : ALIGN(4)
+  {
+    *(.sram2 .sram2.*);
+  } > SRAM2
+} INSERT AFTER .bss;
+
 /* This is where the call stack will be allocated. */
 /* The stack is of the full descending type. */
 /* You may want to use this variable to locate the call stack and static
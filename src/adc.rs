@@ -0,0 +1,165 @@
+//! Analog to digital converter
+//!
+//! Only the internal channels of ADC1 are covered for now: the internal
+//! voltage reference, the temperature sensor and VBAT/3. Readings are
+//! converted with the factory calibration values stored in system memory.
+
+use core::ptr;
+
+use cortex_m::asm;
+
+use crate::flash;
+use crate::rcc::{Clocks, AHB2};
+use crate::stm32::ADC1;
+#[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+use crate::stm32::ADC123_COMMON as ADC_COMMON;
+#[cfg(not(any(feature = "stm32l4x5", feature = "stm32l4x6")))]
+use crate::stm32::ADC_COMMON;
+
+/// Raw VREFINT reading at VDDA = 3.0 V
+const VREFINT_CAL: *const u16 = 0x1FFF_75AA as *const u16;
+/// Raw temperature sensor reading at 30 °C and VDDA = 3.0 V
+const TS_CAL1: *const u16 = 0x1FFF_75A8 as *const u16;
+/// Raw temperature sensor reading at `ts_cal2_temp()` and VDDA = 3.0 V
+const TS_CAL2: *const u16 = 0x1FFF_75CA as *const u16;
+
+const VDDA_CAL_MV: u32 = 3000;
+const TS_CAL1_TEMP: i32 = 30;
+
+/// Full scale of a 12-bit conversion
+const FULL_SCALE: u32 = 4095;
+
+/// ADC1 input channels of the internal sources
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Channel {
+    Vrefint = 0,
+    Temperature = 17,
+    Vbat = 18,
+}
+
+/// ADC1, converting the internal channels
+pub struct Adc {
+    adc: ADC1,
+    common: ADC_COMMON,
+    clocks: Clocks,
+}
+
+impl Adc {
+    /// Powers up, calibrates and enables ADC1
+    ///
+    /// ADC1 runs from its kernel clock if one is selected with
+    /// `rcc::CFGR::adc_src`, from HCLK/4 otherwise.
+    pub fn adc1(adc: ADC1, common: ADC_COMMON, ahb: &mut AHB2, clocks: &Clocks) -> Self {
+        ahb.enr().modify(|_, w| w.adcen().set_bit());
+        ahb.rstr().modify(|_, w| w.adcrst().set_bit());
+        ahb.rstr().modify(|_, w| w.adcrst().clear_bit());
+
+        // CKMODE: 00 kernel clock, 11 HCLK/4
+        let ckmode = if clocks.adcclk().is_some() { 0b00 } else { 0b11 };
+        common.ccr.modify(|_, w| unsafe { w.ckmode().bits(ckmode) });
+
+        // leave deep power-down and start the voltage regulator
+        adc.cr.modify(|_, w| w.deeppwd().clear_bit());
+        adc.cr.modify(|_, w| w.advregen().set_bit());
+        // tADCVREG_STUP is 20 µs
+        asm::delay(clocks.sysclk().0 / 50_000);
+
+        // single-ended calibration
+        adc.cr.modify(|_, w| w.adcaldif().clear_bit().adcal().set_bit());
+        while adc.cr.read().adcal().bit_is_set() {}
+
+        adc.isr.write(|w| w.adrdy().set_bit());
+        adc.cr.modify(|_, w| w.aden().set_bit());
+        while adc.isr.read().adrdy().bit_is_clear() {}
+
+        // longest sampling time (640.5 cycles) on the internal channels, which
+        // need at least 5 µs (temperature) to 12 µs (VBAT)
+        adc.smpr1.modify(|r, w| unsafe { w.bits(r.bits() | 0b111) });
+        adc.smpr2
+            .modify(|r, w| unsafe { w.bits(r.bits() | (0b111 << 21) | (0b111 << 24)) });
+
+        Adc {
+            adc,
+            common,
+            clocks: *clocks,
+        }
+    }
+
+    /// Returns the raw internal voltage reference reading
+    pub fn read_vrefint(&mut self) -> u16 {
+        self.common.ccr.modify(|_, w| w.vrefen().set_bit());
+        let raw = self.convert(Channel::Vrefint);
+        self.common.ccr.modify(|_, w| w.vrefen().clear_bit());
+        raw
+    }
+
+    /// Returns VDDA in millivolts, measured against the internal reference
+    ///
+    /// Returns `None` if the reference reads as 0, which VDDA cannot explain.
+    pub fn read_vdda(&mut self) -> Option<u32> {
+        let raw = u32::from(self.read_vrefint());
+        let cal = u32::from(unsafe { ptr::read(VREFINT_CAL) });
+        (VDDA_CAL_MV * cal).checked_div(raw)
+    }
+
+    /// Returns VBAT in millivolts, `None` if VDDA cannot be measured
+    ///
+    /// The VBAT/3 bridge is only connected during the conversion, as it
+    /// drains the battery.
+    pub fn read_vbat(&mut self) -> Option<u32> {
+        let vdda = self.read_vdda()?;
+        self.common.ccr.modify(|_, w| w.ch18sel().set_bit());
+        let raw = u32::from(self.convert(Channel::Vbat));
+        self.common.ccr.modify(|_, w| w.ch18sel().clear_bit());
+        Some(3 * raw * vdda / FULL_SCALE)
+    }
+
+    /// Returns the die temperature in degrees Celsius, `None` if VDDA cannot
+    /// be measured
+    pub fn read_temperature(&mut self) -> Option<i32> {
+        let vdda = self.read_vdda()?;
+        self.common.ccr.modify(|_, w| w.ch17sel().set_bit());
+        // tSTART of the sensor is 120 µs
+        asm::delay(self.clocks.sysclk().0 / 1_000_000 * 120);
+        let raw = u32::from(self.convert(Channel::Temperature));
+        self.common.ccr.modify(|_, w| w.ch17sel().clear_bit());
+
+        let (cal1, cal2) = unsafe { (i32::from(ptr::read(TS_CAL1)), i32::from(ptr::read(TS_CAL2))) };
+        Some(temperature(raw, vdda, cal1, cal2))
+    }
+
+    /// Releases the ADC1 and ADC common peripherals
+    pub fn free(self) -> (ADC1, ADC_COMMON) {
+        self.adc.cr.modify(|_, w| w.addis().set_bit());
+        (self.adc, self.common)
+    }
+
+    fn convert(&mut self, channel: Channel) -> u16 {
+        // a single conversion: L = 0, SQ1 = channel
+        self.adc
+            .sqr1
+            .write(|w| unsafe { w.bits((channel as u32) << 6) });
+        self.adc.isr.write(|w| w.eoc().set_bit());
+        self.adc.cr.modify(|_, w| w.adstart().set_bit());
+        while self.adc.isr.read().eoc().bit_is_clear() {}
+        self.adc.dr.read().bits() as u16
+    }
+}
+
+/// Temperature from a raw reading taken at `vdda` millivolts, interpolated
+/// between the two calibration points
+fn temperature(raw: u32, vdda: u32, cal1: i32, cal2: i32) -> i32 {
+    // the calibration values were acquired at VDDA = 3.0 V
+    let scaled = (raw * vdda / VDDA_CAL_MV) as i32;
+    (ts_cal2_temp() - TS_CAL1_TEMP) * (scaled - cal1) / (cal2 - cal1) + TS_CAL1_TEMP
+}
+
+/// Temperature of the second calibration point: 110 °C on the STM32L47x/L48x,
+/// 130 °C on the other lines, STM32L49x/L4Ax included
+fn ts_cal2_temp() -> i32 {
+    if flash::dev_id() == flash::DEV_ID_L47X {
+        110
+    } else {
+        130
+    }
+}
@@ -21,6 +21,11 @@ impl Delay {
         Delay { syst, clocks }
     }
 
+    /// Re-times the delays after the clocks were changed by `rcc::reconfigure`
+    pub fn set_clocks(&mut self, clocks: Clocks) {
+        self.clocks = clocks;
+    }
+
     /// Releases the system timer (SysTick) resource
     pub fn free(self) -> SYST {
         self.syst
@@ -47,7 +52,8 @@ impl DelayMs<u8> for Delay {
 
 impl DelayUs<u32> for Delay {
     fn delay_us(&mut self, us: u32) {
-        let rvr = us * (self.clocks.sysclk().0 / 1_000_000);
+        // SysTick runs from the core clock, i.e. HCLK
+        let rvr = us * (self.clocks.hclk().0 / 1_000_000);
 
         // assert!(rvr < (1 << 24)); //TODO fix this assertion
 
@@ -1,29 +1,130 @@
 //! Flash memory
-
+//!
+//! `Flash::new` returns a `Flash<Locked>`, which can only be read. Programming
+//! and erase are available on the `Flash<Unlocked>` returned by `unlock`, the
+//! option bytes on the `OptionBytes<Unlocked>` obtained from it. Both lock
+//! again when dropped.
+
+use core::convert::TryFrom;
+use core::marker::PhantomData;
+use core::mem;
+use core::ptr;
+
+use crate::rcc::Clocks;
 use crate::stm32::FLASH;
 
-#[cfg(feature = "extra-traits")]
 use byteorder::ByteOrder;
 
 #[cfg(feature = "extra-traits")]
-use crate::hal::flash::{FlashError, FlashResult, Read, WriteErase, LockingImpl, Locking};
-// #[cfg(feature = "extra-traits")]
-// use crate::hal::flash::{UnlockGuard, UnlockResult};
+use crate::hal::flash::{Read, WriteErase};
 
 #[cfg(feature = "extra-traits")]
 // use generic_array::{ArrayLength, GenericArray};
 use generic_array::GenericArray;
 
-#[allow(dead_code)]
-pub struct Flash {
-    flash: FLASH
+#[cfg(feature = "embedded-storage")]
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+/// Lock state of `Flash` and `OptionBytes`
+pub trait State {
+    #[doc(hidden)]
+    const LOCKED: bool;
+}
+
+/// Programming and erase disabled
+pub struct Locked;
+/// Programming and erase enabled
+pub struct Unlocked;
+
+impl State for Locked {
+    const LOCKED: bool = true;
+}
+
+impl State for Unlocked {
+    const LOCKED: bool = false;
+}
+
+pub struct Flash<STATE: State = Locked> {
+    flash: FLASH,
+    fault_address: Option<usize>,
+    // banks mass erased through this handle and since only fast programmed
+    erased_banks: [bool; 2],
+    _state: PhantomData<STATE>,
 }
 
 impl Flash {
     // the new constructor approach
-    pub fn new(flash: FLASH) -> Flash {
+    /// Takes the FLASH peripheral, making sure it is locked
+    pub fn new(flash: FLASH) -> Flash<Locked> {
+        flash.cr.modify(|_, w| w.lock().set_bit());
         Self {
-            flash: flash
+            flash: flash,
+            fault_address: None,
+            erased_banks: [false; 2],
+            _state: PhantomData,
+        }
+    }
+}
+
+impl<STATE: State> Flash<STATE> {
+    /// Locks the flash and releases the FLASH peripheral
+    pub fn free(self) -> FLASH {
+        self.flash.cr.modify(|_, w| w.lock().set_bit());
+        self.take()
+    }
+
+    fn into_state<NEW: State>(self) -> Flash<NEW> {
+        let fault_address = self.fault_address;
+        let erased_banks = self.erased_banks;
+        Flash {
+            flash: self.take(),
+            fault_address,
+            erased_banks,
+            _state: PhantomData,
+        }
+    }
+
+    /// Returns the address of the last error
+    ///
+    /// That is the double word with an ECC error if one was detected since
+    /// the error flags were cleared, otherwise the address being programmed
+    /// or erased when the last operation failed.
+    pub fn fault_address(&self) -> Option<usize> {
+        let eccr = self.flash.eccr.read().bits();
+        if eccr & (ECCR_ECCC | ECCR_ECCD) != 0 {
+            Some(ecc_address(eccr, self.geometry()))
+        } else {
+            self.fault_address
+        }
+    }
+
+    /// Clears the error flags of FLASH_SR and FLASH_ECCR
+    ///
+    /// Done before each program and erase operation, as a pending error flag
+    /// prevents further operations.
+    pub fn clear_errors(&mut self) {
+        unsafe {
+            self.flash.sr.write(|w| w.bits(SR_ERRORS));
+            self.flash
+                .eccr
+                .modify(|r, w| w.bits(r.bits() | ECCR_ECCC | ECCR_ECCD));
+        }
+    }
+
+    // moves the peripheral out without running `drop`, which would lock
+    fn take(self) -> FLASH {
+        let flash = unsafe { ptr::read(&self.flash) };
+        mem::forget(self);
+        flash
+    }
+}
+
+impl<STATE: State> Drop for Flash<STATE> {
+    fn drop(&mut self) {
+        if !STATE::LOCKED {
+            self.flash.cr.modify(|_, w| w.lock().set_bit());
         }
     }
 }
@@ -33,88 +134,206 @@ impl Flash {
 
 pub const FLASH_ORIGIN: usize = 0x08000000;
 
-#[cfg(feature = "extra-traits")]
+// FLASH_SR error flags, cleared by writing 1
+const SR_OPERR: u32 = 1 << 1;
+const SR_PROGERR: u32 = 1 << 3;
+const SR_WRPERR: u32 = 1 << 4;
+const SR_PGAERR: u32 = 1 << 5;
+const SR_SIZERR: u32 = 1 << 6;
+const SR_PGSERR: u32 = 1 << 7;
+const SR_MISERR: u32 = 1 << 8;
+const SR_FASTERR: u32 = 1 << 9;
+const SR_RDERR: u32 = 1 << 14;
+const SR_OPTVERR: u32 = 1 << 15;
+const SR_ERRORS: u32 = SR_OPERR
+    | SR_PROGERR
+    | SR_WRPERR
+    | SR_PGAERR
+    | SR_SIZERR
+    | SR_PGSERR
+    | SR_MISERR
+    | SR_FASTERR
+    | SR_RDERR
+    | SR_OPTVERR;
+
+// FLASH_CR and FLASH_SR, for `write_row` which cannot use the PAC accessors
+const FLASH_SR: *const u32 = 0x4002_2010 as *const u32;
+const FLASH_CR: *mut u32 = 0x4002_2014 as *mut u32;
+const SR_BSY: u32 = 1 << 16;
+const CR_FSTPG: u32 = 1 << 18;
+
+// FLASH_CR bits of the second bank, missing from the PAC of the single-bank
+// device features although the STM32L471 has them
+const CR_BKER: u32 = 1 << 11;
+const CR_MER2: u32 = 1 << 15;
+
+// FLASH_ECCR
+const ECCR_ADDR: u32 = 0x7_FFFF;
+const ECCR_BK: u32 = 1 << 19;
+const ECCR_ECCC: u32 = 1 << 30;
+const ECCR_ECCD: u32 = 1 << 31;
+
+/// Flash operation error
+#[derive(Copy, Clone, Debug)]
+pub enum FlashError {
+    /// Flash program and erase controller failed to unlock
+    UnlockFailed,
+    /// Address to be programmed contains a value different from '0xFFFF' before programming
+    ProgrammingError,
+    /// Programming a write-protected address of the Flash memory
+    WriteProtectionError,
+    /// Programming and erase controller is busy
+    Busy,
+    /// Address or length not a multiple of the native write or erase size
+    NotAligned,
+    /// Address outside of the Flash memory
+    OutOfBounds,
+    /// A program or erase operation did not complete
+    OperationError,
+    /// Data not aligned on the programming size, or in a different row
+    ProgrammingAlignmentError,
+    /// Programming or erase sequence not followed, e.g. programming without erase
+    ProgrammingSequenceError,
+    /// Access size not matching the programming size
+    SizeError,
+    /// Fast programming data not written in time
+    FastProgrammingMissError,
+    /// Fast programming aborted, e.g. on a page that was not erased
+    FastProgrammingError,
+    /// Read of a read-protected (PCROP) area
+    ReadProtectionError,
+    /// Uncorrectable two bit error detected by ECC
+    EccError,
+    /// Area to be programmed is not erased
+    NotErased,
+    /// HCLK below 8 MHz, the minimum for fast programming
+    ClockTooSlow,
+}
+
+/// A type alias for the result of a Flash operation.
+pub type FlashResult = Result<(), FlashError>;
+
+/// Decodes the error flags of FLASH_SR, the first match in the order of the
+/// reference manual wins
+fn sr_error(sr: u32) -> Option<FlashError> {
+    let errors = [
+        (SR_OPERR, FlashError::OperationError),
+        (SR_PROGERR, FlashError::ProgrammingError),
+        (SR_WRPERR, FlashError::WriteProtectionError),
+        (SR_PGAERR, FlashError::ProgrammingAlignmentError),
+        (SR_SIZERR, FlashError::SizeError),
+        (SR_PGSERR, FlashError::ProgrammingSequenceError),
+        (SR_MISERR, FlashError::FastProgrammingMissError),
+        (SR_FASTERR, FlashError::FastProgrammingError),
+        (SR_RDERR, FlashError::ReadProtectionError),
+    ];
+    errors
+        .iter()
+        .find(|(flag, _)| sr & flag != 0)
+        .map(|&(_, error)| error)
+}
+
+/// Address of the double word reported in FLASH_ECCR
+fn ecc_address(eccr: u32, geometry: Geometry) -> usize {
+    // BK_ECC is only ever set on dual bank devices
+    let bank = if eccr & ECCR_BK != 0 { Bank::Bank2 } else { Bank::Bank1 };
+    geometry.page_address(bank, 0) + (eccr & ECCR_ADDR) as usize
+}
+
 const FLASH_KEY1: u32 = 0x4567_0123;
-#[cfg(feature = "extra-traits")]
 const FLASH_KEY2: u32 = 0xCDEF_89AB;
-#[cfg(feature = "extra-traits")]
 const OPTION_BYTES_FLASH_KEY1: u32 = 0x0819_2A3B;
-#[cfg(feature = "extra-traits")]
 const OPTION_BYTES_FLASH_KEY2: u32 = 0x4C5D_6E7F;
 
-#[cfg(all(feature = "stm32l4x2", feature="extra-traits"))]
-impl LockingImpl for Flash {
-    fn is_locked(&self) -> bool {
-        self.flash.cr.read().lock().bit_is_set()
-    }
-
-    /// unlocks the Flash.
-    fn unlock(&mut self) {
-        // ehh.. should check BSY here
-        // peripheral stalls if it's already locked, so check
-        if self.is_locked() {
-            unsafe {
-                self.flash.keyr.write(|w| w.keyr().bits(FLASH_KEY1));
-                self.flash.keyr.write(|w| w.keyr().bits(FLASH_KEY2));
-            }
+impl Flash<Locked> {
+    /// Unlocks programming and erase
+    ///
+    /// The flash locks again when the returned `Flash<Unlocked>` is dropped,
+    /// or with `lock`. On failure the locked flash is given back, it can still
+    /// be read; a wrong key sequence keeps it locked until the next reset.
+    pub fn unlock(self) -> Result<Flash<Unlocked>, (Flash<Locked>, FlashError)> {
+        while self.flash.sr.read().bsy().bit_is_set() {}
+        unsafe {
+            self.flash.keyr.write(|w| w.keyr().bits(FLASH_KEY1));
+            self.flash.keyr.write(|w| w.keyr().bits(FLASH_KEY2));
         }
+        if self.flash.cr.read().lock().bit_is_set() {
+            return Err((self, FlashError::UnlockFailed));
+        }
+        Ok(self.into_state())
     }
+}
 
-    /// locks the flash
-    fn lock(&mut self) {
+impl Flash<Unlocked> {
+    /// Locks programming and erase
+    pub fn lock(self) -> Flash<Locked> {
         self.flash.cr.modify(|_, w| w.lock().set_bit());
+        self.into_state()
     }
-}
 
-#[cfg(all(feature = "stm32l4x2", feature="extra-traits"))]
-impl Locking for Flash {}
+    /// Gives access to the option bytes, which are unlocked separately
+    pub fn option_bytes(&mut self) -> OptionBytes<Locked> {
+        OptionBytes {
+            flash: &self.flash,
+            _state: PhantomData,
+        }
+    }
+}
 
-pub trait OptionBytesLocking {
-    fn option_bytes_are_locked(&self) -> bool;
-    fn option_bytes_unlock(&self);
-    fn option_bytes_lock(&self);
+/// Option bytes, borrowed from an unlocked `Flash`
+pub struct OptionBytes<'a, STATE: State = Locked> {
+    flash: &'a FLASH,
+    _state: PhantomData<STATE>,
 }
 
-#[cfg(all(feature = "stm32l4x2", feature="extra-traits"))]
-impl OptionBytesLocking for Flash {
-    fn option_bytes_are_locked(&self) -> bool {
-        self.flash.cr.read().optlock().bit_is_set()
-    }
-
-    /// unlocks the Flash.
-    fn option_bytes_unlock(&self) {
-        // need to make OptionBytesLocking aware of Locking trait
-        // if self.locked() {
-        //     self.unlock();
-        // }
-        if self.option_bytes_are_locked() {
-            unsafe {
-                self.flash.optkeyr.write(|w| w.optkeyr().bits(OPTION_BYTES_FLASH_KEY1));
-                self.flash.optkeyr.write(|w| w.optkeyr().bits(OPTION_BYTES_FLASH_KEY2));
-            }
+impl<'a, STATE: State> OptionBytes<'a, STATE> {
+    fn into_state<NEW: State>(self) -> OptionBytes<'a, NEW> {
+        let flash = self.flash;
+        // skip `drop`, which would lock
+        mem::forget(self);
+        OptionBytes {
+            flash,
+            _state: PhantomData,
         }
     }
+}
 
-    /// locks the flash
-    fn option_bytes_lock(&self) {
-        self.flash.cr.modify(|_, w| w.optlock().set_bit());
+impl<'a, STATE: State> Drop for OptionBytes<'a, STATE> {
+    fn drop(&mut self) {
+        if !STATE::LOCKED {
+            self.flash.cr.modify(|_, w| w.optlock().set_bit());
+        }
     }
 }
 
-impl Flash {
-    pub fn get_rdp(&self) -> u8 {
-        let rdp_bits = self.flash.optr.read().rdp().bits();
-        match rdp_bits {
-            0xAA => 0,
-            0xCC => 2,
-            _ => 1,
+impl<'a> OptionBytes<'a, Locked> {
+    /// Unlocks option byte programming
+    ///
+    /// The option bytes lock again when the returned `OptionBytes<Unlocked>`
+    /// is dropped, or with `lock`. On failure the locked option bytes are
+    /// given back.
+    pub fn unlock(self) -> Result<OptionBytes<'a, Unlocked>, (OptionBytes<'a, Locked>, FlashError)> {
+        unsafe {
+            self.flash.optkeyr.write(|w| w.optkeyr().bits(OPTION_BYTES_FLASH_KEY1));
+            self.flash.optkeyr.write(|w| w.optkeyr().bits(OPTION_BYTES_FLASH_KEY2));
+        }
+        if self.flash.cr.read().optlock().bit_is_set() {
+            return Err((self, FlashError::UnlockFailed));
         }
+        Ok(self.into_state())
+    }
+}
+
+impl<'a> OptionBytes<'a, Unlocked> {
+    /// Locks option byte programming
+    pub fn lock(self) -> OptionBytes<'a, Locked> {
+        self.flash.cr.modify(|_, w| w.optlock().set_bit());
+        self.into_state()
     }
 
     // NB: only effects system after power reset
     // (alternatively, could set FLASH.CR.OBL_LAUNCH)
-    #[cfg(all(feature = "stm32l4x2", feature="extra-traits"))]
-    pub fn set_rdp(&self, level: u8) {
+    pub fn set_rdp(&mut self, level: u8) {
         assert!(level <= 2);
 
         let rdp_bits = match level {
@@ -123,7 +342,6 @@ impl Flash {
             _ => 0  // anything other than AA and CC
         };
 
-        self.option_bytes_unlock();
         unsafe { self.flash.optr.modify(|_, w| w.rdp().bits(rdp_bits)); }
 
         // initiate writing
@@ -145,7 +363,7 @@ impl Flash {
     // $ openocd.cfg -c "program something.elf verify reset exit
     // while releasing reset button, where something.elf is a binary
     // that fixes the boot flags
-    pub fn set_boot_from_rom(&self) {
+    pub fn set_boot_from_rom(&mut self) {
         self.flash.optr.modify(|_, w| w.n_swboot0().clear_bit());
         self.flash.optr.modify(|_, w| w.n_boot0().clear_bit());
         // would reset immediately
@@ -153,13 +371,44 @@ impl Flash {
     }
 
     // cf. set_rdp
-    pub fn set_boot_from_flash(&self) {
+    pub fn set_boot_from_flash(&mut self) {
         self.flash.optr.modify(|_, w| w.n_swboot0().clear_bit());
         self.flash.optr.modify(|_, w| w.n_boot0().set_bit());
         // would reset immediately
         // self.flash.cr.modify(|_, w| w.obl_launch().set_bit());
     }
 
+    /// Sets whether the independent watchdog keeps counting in Stop and
+    /// Standby modes (IWDG_STOP, IWDG_STDBY option bits)
+    ///
+    /// Takes effect after the next option byte loading, cf. set_rdp.
+    pub fn set_iwdg_freeze(&mut self, freeze_in_stop: bool, freeze_in_standby: bool) {
+        // a cleared bit freezes the counter
+        self.flash.optr.modify(|_, w| {
+            w.iwdg_stop()
+                .bit(!freeze_in_stop)
+                .iwdg_stdby()
+                .bit(!freeze_in_standby)
+        });
+
+        // initiate writing
+        self.flash.cr.modify(|_, w| w.optstrt().set_bit());
+
+        // wait until done
+        while self.flash.sr.read().bsy().bit_is_set() {}
+    }
+}
+
+impl<STATE: State> Flash<STATE> {
+    pub fn get_rdp(&self) -> u8 {
+        let rdp_bits = self.flash.optr.read().rdp().bits();
+        match rdp_bits {
+            0xAA => 0,
+            0xCC => 2,
+            _ => 1,
+        }
+    }
+
     pub fn get_boot_bits(&self) -> (bool, bool, bool) {
         (
             self.flash.optr.read().n_boot0().bit(),
@@ -172,49 +421,258 @@ impl Flash {
     }
 }
 
-#[cfg(feature = "stm32l4x2")]
-pub const READ_SIZE: usize = 8;
-#[cfg(feature = "stm32l4x2")]
+/// Read granularity, the flash is memory mapped
+pub const READ_SIZE: usize = 1;
+/// Programming granularity, a double word
 pub const WRITE_SIZE: usize = 8;
-#[cfg(feature = "stm32l4x2")]
+/// Page size, identical on all STM32L4 devices
 pub const PAGE_SIZE: usize = 2048;
+/// Fast programming row size, 32 double words
+pub const ROW_SIZE: usize = 256;
+const ROW_WORDS: usize = ROW_SIZE / 4;
+/// Slowest HCLK allowed for fast programming
+const FAST_PROGRAM_HCLK_MIN: u32 = 8_000_000;
+
+/// Address of the flash size in KB, programmed in the factory (FLASHSIZE_DATA)
+const FLASHSIZE_DATA: *const u16 = 0x1FFF_75E0 as *const u16;
+/// Address of the MCU device ID code (DBGMCU_IDCODE)
+const DBGMCU_IDCODE: *const u32 = 0xE004_2000 as *const u32;
+/// Device ID of the STM32L47x/L48x
+pub(crate) const DEV_ID_L47X: u32 = 0x415;
+/// Device ID of the STM32L49x/L4Ax
+pub(crate) const DEV_ID_L49X: u32 = 0x461;
+/// DUALBANK option bit of the dual-bank lines
+const OPTR_DUALBANK: u32 = 1 << 21;
+
+/// Flash bank
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bank {
+    Bank1,
+    Bank2,
+}
 
-#[cfg(all(feature = "stm32l4x2", feature="extra-traits"))]
-// impl Read for Flash {
-impl Read<generic_array::typenum::U8> for Flash {
-    // flash read is two consecutive u32 words, aligned
-    fn read_native(&self, address: usize, array: &mut GenericArray<u8, generic_array::typenum::U8>) {
-        unsafe {
-            byteorder::NativeEndian::write_u32(
-                &mut array.as_mut_slice()[..4],
-                core::ptr::read_volatile(address as *mut u32),
-            );
-            byteorder::NativeEndian::write_u32(
-                &mut array.as_mut_slice()[4..],
-                core::ptr::read_volatile((address + 4) as *mut u32),
-            );
+/// Flash memory organization of the device
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Geometry {
+    /// Size in bytes
+    pub size: usize,
+    /// Erase granularity in bytes
+    pub page_size: usize,
+    /// Number of banks, 1 or 2
+    pub banks: u8,
+}
+
+impl Geometry {
+    /// Returns the number of pages of each bank
+    pub fn pages_per_bank(&self) -> usize {
+        self.size / usize::from(self.banks) / self.page_size
+    }
+
+    /// Returns whether the device has `bank`
+    pub fn has_bank(&self, bank: Bank) -> bool {
+        bank == Bank::Bank1 || self.banks == 2
+    }
+
+    /// Returns the address of `page` in `bank`
+    ///
+    /// Assumes the banks are not swapped (BFB2 option bit).
+    pub fn page_address(&self, bank: Bank, page: u8) -> usize {
+        let bank_offset = match bank {
+            Bank::Bank1 => 0,
+            Bank::Bank2 => self.size / usize::from(self.banks),
+        };
+        FLASH_ORIGIN + bank_offset + usize::from(page) * self.page_size
+    }
+
+    /// Returns the bank and page containing `address`, if in flash
+    pub fn page_of(&self, address: usize) -> Option<(Bank, u8)> {
+        if address < FLASH_ORIGIN || address >= FLASH_ORIGIN + self.size {
+            return None;
         }
+        let page = (address - FLASH_ORIGIN) / self.page_size;
+        let pages_per_bank = self.pages_per_bank();
+        let (bank, page) = if page < pages_per_bank {
+            (Bank::Bank1, page)
+        } else {
+            (Bank::Bank2, page - pages_per_bank)
+        };
+        u8::try_from(page).ok().map(|page| (bank, page))
     }
 }
 
-#[cfg(all(feature = "stm32l4x2", feature="extra-traits"))]
-impl WriteErase<generic_array::typenum::U2048, generic_array::typenum::U8> for Flash {
-    fn status(&self) -> FlashResult {
-        let sr = self.flash.sr.read();
-        if sr.bsy().bit_is_set() {
-            Err(FlashError::Busy)
-        } else if sr.progerr().bit_is_set() {
-            Err(FlashError::ProgrammingError)
-        } else if sr.wrperr().bit_is_set() {
-            Err(FlashError::WriteProtectionError)
+/// Returns the device ID, which tells the product lines apart
+pub(crate) fn dev_id() -> u32 {
+    unsafe { ptr::read_volatile(DBGMCU_IDCODE) & 0xFFF }
+}
+
+impl<STATE: State> Flash<STATE> {
+    /// Returns the flash organization, read from the device
+    pub fn geometry(&self) -> Geometry {
+        let size = usize::from(unsafe { core::ptr::read(FLASHSIZE_DATA) }) * 1024;
+        Geometry {
+            size,
+            page_size: PAGE_SIZE,
+            banks: self.banks(size),
+        }
+    }
+
+    // Only the dual-bank lines have a second bank, whatever the device feature.
+    // Their 1 MB devices always use it, smaller ones depend on the DUALBANK
+    // option bit.
+    fn banks(&self, size: usize) -> u8 {
+        let dev_id = dev_id();
+        let dual_bank = (dev_id == DEV_ID_L47X || dev_id == DEV_ID_L49X)
+            && (size == 1024 * 1024 || self.flash.optr.read().bits() & OPTR_DUALBANK != 0);
+        if dual_bank {
+            2
         } else {
-            Ok(())
+            1
         }
     }
+}
 
-    fn write_native(&mut self, address: usize,
-                    array: &GenericArray<u8, generic_array::typenum::U8>) -> FlashResult {
-        self.status()?;
+impl Flash<Unlocked> {
+    /// Erases `page` of `bank`
+    pub fn erase_bank_page(&mut self, bank: Bank, page: u8) -> FlashResult {
+        let geometry = self.geometry();
+        if !geometry.has_bank(bank) || usize::from(page) >= geometry.pages_per_bank() {
+            return Err(FlashError::OutOfBounds);
+        }
+        self.start_operation()?;
+
+        // select the bank of the page
+        self.flash.cr.modify(|r, w| unsafe {
+            match bank {
+                Bank::Bank1 => w.bits(r.bits() & !CR_BKER),
+                Bank::Bank2 => w.bits(r.bits() | CR_BKER),
+            }
+        });
+
+        // enable page erase
+        self.flash.cr.modify(|_, w| w.per().set_bit());
+        // set page number
+        unsafe { self.flash.cr.modify(|_, w| w.pnb().bits(page)); }
+        // start erase page
+        self.erased_banks[bank as usize] = false;
+        self.flash.cr.modify(|_, w| w.start().set_bit());
+        // wait until done
+        let result = self.finish_operation(geometry.page_address(bank, page));
+        // disable page erase
+        self.flash.cr.modify(|_, w| w.per().clear_bit());
+
+        result
+    }
+
+    /// Erases all pages of `bank`
+    pub fn erase_bank(&mut self, bank: Bank) -> FlashResult {
+        let geometry = self.geometry();
+        if !geometry.has_bank(bank) {
+            return Err(FlashError::OutOfBounds);
+        }
+        self.start_operation()?;
+
+        // enable mass erase of the bank
+        match bank {
+            Bank::Bank1 => self.flash.cr.modify(|_, w| w.mer1().set_bit()),
+            Bank::Bank2 => self.flash.cr.modify(|r, w| unsafe { w.bits(r.bits() | CR_MER2) }),
+        }
+        // start mass erase
+        self.flash.cr.modify(|_, w| w.start().set_bit());
+        // wait until done
+        let result = self.finish_operation(geometry.page_address(bank, 0));
+        // disable mass erase
+        self.flash.cr.modify(|_, w| w.mer1().clear_bit());
+        if geometry.banks == 2 {
+            self.flash.cr.modify(|r, w| unsafe { w.bits(r.bits() & !CR_MER2) });
+        }
+
+        self.erased_banks[bank as usize] = result.is_ok();
+        result
+    }
+
+    /// Programs `data` at `address`, one double word at a time
+    ///
+    /// `address` and the length of `data` must be multiples of `WRITE_SIZE`,
+    /// and the double words erased.
+    pub fn program(&mut self, address: usize, data: &[u8]) -> FlashResult {
+        if address % WRITE_SIZE != 0 || data.len() % WRITE_SIZE != 0 {
+            return Err(FlashError::NotAligned);
+        }
+        for (i, chunk) in data.chunks(WRITE_SIZE).enumerate() {
+            self.program_double_word(address + i * WRITE_SIZE, chunk)?;
+        }
+        Ok(())
+    }
+
+    /// Programs `data` in fast programming mode, row by row
+    ///
+    /// `address` and the length of `data` must be multiples of `ROW_SIZE`,
+    /// see `fast_program_row`.
+    pub fn fast_program(&mut self, address: usize, data: &[u8], clocks: &Clocks) -> FlashResult {
+        if address % ROW_SIZE != 0 || data.len() % ROW_SIZE != 0 {
+            return Err(FlashError::NotAligned);
+        }
+        let mut row = [0u8; ROW_SIZE];
+        for (i, chunk) in data.chunks(ROW_SIZE).enumerate() {
+            row.copy_from_slice(chunk);
+            self.fast_program_row(address + i * ROW_SIZE, &row, clocks)?;
+        }
+        Ok(())
+    }
+
+    /// Programs the 256 byte row at `address` in fast programming mode
+    ///
+    /// The reference manual only allows fast programming in a bank that was
+    /// mass erased, the hardware raises PGSERR otherwise. Hence the bank must
+    /// have been erased with `erase_bank` (or `erase_all_pages`) through this
+    /// handle, and only fast programmed since, or `FlashError::NotErased` is
+    /// returned. The row is checked to be erased as well, as programming over
+    /// existing data would corrupt it and its ECC.
+    ///
+    /// HCLK must be at least 8 MHz. Interrupts are disabled while the row is
+    /// written.
+    pub fn fast_program_row(
+        &mut self,
+        address: usize,
+        data: &[u8; ROW_SIZE],
+        clocks: &Clocks,
+    ) -> FlashResult {
+        if clocks.hclk().0 < FAST_PROGRAM_HCLK_MIN {
+            return Err(FlashError::ClockTooSlow);
+        }
+        if address % ROW_SIZE != 0 {
+            return Err(FlashError::NotAligned);
+        }
+        let (bank, _) = self.geometry().page_of(address).ok_or(FlashError::OutOfBounds)?;
+
+        let erased = self.erased_banks[bank as usize]
+            && (0..ROW_WORDS).all(|i| unsafe {
+                ptr::read_volatile((address as *const u32).add(i)) == 0xFFFF_FFFF
+            });
+        if !erased {
+            self.fault_address = Some(address);
+            return Err(FlashError::NotErased);
+        }
+
+        let mut words = [0u32; ROW_WORDS];
+        for (word, bytes) in words.iter_mut().zip(data.chunks(4)) {
+            *word = byteorder::NativeEndian::read_u32(bytes);
+        }
+
+        self.start_operation()?;
+        cortex_m::interrupt::free(|_| unsafe { write_row(address, words.as_ptr()) });
+        // disable fast programming
+        self.flash.cr.modify(|_, w| w.fstpg().clear_bit());
+
+        self.finish_operation(address)
+    }
+
+    // programs the double word at `address` with the 8 bytes of `bytes`
+    fn program_double_word(&mut self, address: usize, bytes: &[u8]) -> FlashResult {
+        self.start_operation()?;
+        if let Some((bank, _)) = self.geometry().page_of(address) {
+            self.erased_banks[bank as usize] = false;
+        }
 
         // enable programming
         self.flash.cr.modify(|_, w| w.pg().set_bit());
@@ -224,58 +682,240 @@ impl WriteErase<generic_array::typenum::U2048, generic_array::typenum::U8> for F
             // Program the first word
             core::ptr::write_volatile(
                 address as *mut u32,
-                byteorder::NativeEndian::read_u32(&array.as_slice()[..4])
+                byteorder::NativeEndian::read_u32(&bytes[..4])
             );
             // Program the second word
             core::ptr::write_volatile(
                 (address + 4) as *mut u32,
-                byteorder::NativeEndian::read_u32(&array.as_slice()[4..])
+                byteorder::NativeEndian::read_u32(&bytes[4..8])
             );
         }
 
         // wait until done
-        while self.flash.sr.read().bsy().bit_is_set() {}
+        let result = self.finish_operation(address);
 
         // disable programming
         self.flash.cr.modify(|_, w| w.pg().clear_bit());
 
-        match self.flash.sr.read().bits() {
-            0 => Ok(()),
-            _ => Err(FlashError::ProgrammingError),
+        result
+    }
+
+    // checks the flash is idle and clears stale error flags
+    fn start_operation(&mut self) -> FlashResult {
+        if self.flash.sr.read().bsy().bit_is_set() {
+            return Err(FlashError::Busy);
         }
+        self.clear_errors();
+        self.fault_address = None;
+        Ok(())
     }
 
-    // TODO: use critical section?
-    fn erase_page(&mut self, page: u8) -> FlashResult {
-        self.status()?;
+    // waits for the operation on `address` to complete and reports its errors
+    fn finish_operation(&mut self, address: usize) -> FlashResult {
+        while self.flash.sr.read().bsy().bit_is_set() {}
+        match sr_error(self.flash.sr.read().bits()) {
+            Some(error) => {
+                self.fault_address = Some(address);
+                Err(error)
+            }
+            None => Ok(()),
+        }
+    }
+}
 
-        // enable page erase
-        self.flash.cr.modify(|_, w| w.per().set_bit());
-        // set page number
-        unsafe { self.flash.cr.modify(|_, w| w.pnb().bits(page)); }
-        // start erase page
-        self.flash.cr.modify(|_, w| w.start().set_bit());
-        // wait until done
-        while !self.flash.sr.read().bsy().bit_is_clear() {}
-        // disable page erase
-        self.flash.cr.modify(|_, w| w.per().clear_bit());
+/// Writes a row in fast programming mode
+///
+/// Fetching code from the flash while the row is being written delays the
+/// next double word, which aborts the programming with MISERR. Hence this
+/// function is placed in `.data`, which is copied to RAM at startup, and must
+/// be called with interrupts disabled. It is written in assembly so that no
+/// opt-level can turn it into calls back into the flash; `tools/check.py
+/// write_row` verifies this on the built library.
+#[inline(never)]
+#[link_section = ".data.stm32l4xx_hal.write_row"]
+unsafe fn write_row(address: usize, words: *const u32) {
+    #[cfg(target_arch = "arm")]
+    core::arch::asm!(
+        // set FSTPG
+        "ldr {tmp}, [{cr}]",
+        "orr {tmp}, {tmp}, {fstpg}",
+        "str {tmp}, [{cr}]",
+        // copy the row, one word at a time
+        "2:",
+        "ldr {tmp}, [{src}], #4",
+        "str {tmp}, [{dst}], #4",
+        "subs {n}, {n}, #1",
+        "bne 2b",
+        // wait while BSY is set
+        "3:",
+        "ldr {tmp}, [{sr}]",
+        "tst {tmp}, {bsy}",
+        "bne 3b",
+        cr = in(reg) FLASH_CR,
+        sr = in(reg) FLASH_SR,
+        fstpg = in(reg) CR_FSTPG,
+        bsy = in(reg) SR_BSY,
+        src = inout(reg) words => _,
+        dst = inout(reg) address => _,
+        n = inout(reg) ROW_WORDS => _,
+        tmp = out(reg) _,
+        options(nostack),
+    );
+
+    #[cfg(not(target_arch = "arm"))]
+    {
+        let _ = (address, words);
+        unreachable!("fast programming requires the STM32L4 flash");
+    }
+}
 
+#[cfg(feature = "extra-traits")]
+// impl Read for Flash {
+impl<STATE: State> Read<generic_array::typenum::U8> for Flash<STATE> {
+    // flash read is two consecutive u32 words, aligned
+    fn read_native(&self, address: usize, array: &mut GenericArray<u8, generic_array::typenum::U8>) {
+        unsafe {
+            byteorder::NativeEndian::write_u32(
+                &mut array.as_mut_slice()[..4],
+                core::ptr::read_volatile(address as *mut u32),
+            );
+            byteorder::NativeEndian::write_u32(
+                &mut array.as_mut_slice()[4..],
+                core::ptr::read_volatile((address + 4) as *mut u32),
+            );
+        }
+    }
+}
+
+#[cfg(feature = "extra-traits")]
+impl WriteErase<generic_array::typenum::U2048, generic_array::typenum::U8> for Flash<Unlocked> {
+    fn status(&self) -> FlashResult {
+        let sr = self.flash.sr.read();
+        if sr.bsy().bit_is_set() {
+            return Err(FlashError::Busy);
+        }
+        if let Some(error) = sr_error(sr.bits()) {
+            return Err(error);
+        }
+        if self.flash.eccr.read().bits() & ECCR_ECCD != 0 {
+            return Err(FlashError::EccError);
+        }
         Ok(())
     }
 
+    fn write_native(&mut self, address: usize,
+                    array: &GenericArray<u8, generic_array::typenum::U8>) -> FlashResult {
+        self.program_double_word(address, array.as_slice())
+    }
+
+    // TODO: use critical section?
+    /// Erases `page` of the first bank, see `erase_bank_page`
+    fn erase_page(&mut self, page: u8) -> FlashResult {
+        self.erase_bank_page(Bank::Bank1, page)
+    }
+
+    /// Erases all banks
     fn erase_all_pages(&mut self) -> FlashResult {
-        self.status()?;
+        let dual_bank = self.geometry().banks == 2;
+        self.start_operation()?;
 
         // enable mass erase
         self.flash.cr.modify(|_, w| w.mer1().set_bit());
+        if dual_bank {
+            self.flash.cr.modify(|r, w| unsafe { w.bits(r.bits() | CR_MER2) });
+        }
         // start mass erase
         self.flash.cr.modify(|_, w| w.start().set_bit());
         // wait until done
-        while !self.flash.sr.read().bsy().bit_is_clear() {}
+        let result = self.finish_operation(FLASH_ORIGIN);
         // disable mass erase
         self.flash.cr.modify(|_, w| w.mer1().clear_bit());
+        if dual_bank {
+            self.flash.cr.modify(|r, w| unsafe { w.bits(r.bits() & !CR_MER2) });
+        }
+
+        self.erased_banks = [result.is_ok(); 2];
+        result
+    }
+
+}
+
+// embedded-storage traits, offsets are relative to `FLASH_ORIGIN`.
+//
+// Reads are plain memory accesses and work on a locked flash, while writes
+// and erases need a `Flash<Unlocked>`.
+// A double word can only be programmed once between erases (ECC), so
+// `MultiwriteNorFlash` is not implemented.
+
+#[cfg(feature = "embedded-storage")]
+impl NorFlashError for FlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            FlashError::NotAligned => NorFlashErrorKind::NotAligned,
+            FlashError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            _ => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(feature = "embedded-storage")]
+impl<STATE: State> ErrorType for Flash<STATE> {
+    type Error = FlashError;
+}
 
+#[cfg(feature = "embedded-storage")]
+impl<STATE: State> ReadNorFlash for Flash<STATE> {
+    const READ_SIZE: usize = READ_SIZE;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), FlashError> {
+        let offset = offset as usize;
+        match offset.checked_add(bytes.len()) {
+            Some(end) if end <= self.capacity() => {}
+            _ => return Err(FlashError::OutOfBounds),
+        }
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = unsafe { core::ptr::read_volatile((FLASH_ORIGIN + offset + i) as *const u8) };
+        }
         Ok(())
     }
 
+    fn capacity(&self) -> usize {
+        self.geometry().size
+    }
+}
+
+#[cfg(feature = "embedded-storage")]
+impl NorFlash for Flash<Unlocked> {
+    const WRITE_SIZE: usize = WRITE_SIZE;
+    const ERASE_SIZE: usize = PAGE_SIZE;
+
+    /// Erases the pages from `from` up to, but not including, `to`
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), FlashError> {
+        let (from, to) = (from as usize, to as usize);
+        let geometry = self.geometry();
+        if from > to || to > geometry.size {
+            return Err(FlashError::OutOfBounds);
+        }
+        if from % PAGE_SIZE != 0 || to % PAGE_SIZE != 0 {
+            return Err(FlashError::NotAligned);
+        }
+
+        for offset in (from..to).step_by(PAGE_SIZE) {
+            let (bank, page) = FLASH_ORIGIN
+                .checked_add(offset)
+                .and_then(|address| geometry.page_of(address))
+                .ok_or(FlashError::OutOfBounds)?;
+            self.erase_bank_page(bank, page)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), FlashError> {
+        let offset = offset as usize;
+        match offset.checked_add(bytes.len()) {
+            Some(end) if end <= self.capacity() => {}
+            _ => return Err(FlashError::OutOfBounds),
+        }
+        self.program(FLASH_ORIGIN + offset, bytes)
+    }
 }
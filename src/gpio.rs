@@ -33,6 +33,9 @@ pub struct Output<MODE> {
     _mode: PhantomData<MODE>,
 }
 
+/// Analog mode (type state)
+pub struct Analog;
+
 /// Push pull output (type state)
 pub struct PushPull;
 /// Open drain output (type state)
@@ -50,6 +53,14 @@ pub enum State {
     Low,
 }
 
+/// Pull applied to a pin in Standby and Shutdown modes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pull {
+    None,
+    Up,
+    Down,
+}
+
 /// Alternate function 0 (type state)
 pub struct AF0;
 
@@ -99,7 +110,7 @@ pub struct AF14;
 pub struct AF15;
 
 macro_rules! gpio {
-    ($GPIOX:ident, $gpiox:ident, $gpioy:ident, $iopxenr:ident, $iopxrst:ident, $PXx:ident, [
+    ($GPIOX:ident, $gpiox:ident, $gpioy:ident, $iopxenr:ident, $iopxrst:ident, $pucrx:ident, $pdcrx:ident, $PXx:ident, [
         $($PXi:ident: ($pxi:ident, $i:expr, $MODE:ty, $AFR:ident),)+
     ]) => {
         /// GPIO
@@ -107,14 +118,15 @@ macro_rules! gpio {
             use core::marker::PhantomData;
 
             use crate::hal::digital::{OutputPin, InputPin};
-            use crate::stm32::{$gpioy, $GPIOX};
+            use crate::stm32::{$gpioy, $GPIOX, PWR};
 
+            use crate::pwr::Pwr;
             use crate::rcc::AHB2;
             use super::{
-                Alternate,
-                AF1, AF4, AF5, AF6, AF7, AF8, AF9, AF10,
+                Alternate, Analog,
+                AF0, AF1, AF4, AF5, AF6, AF7, AF8, AF9, AF10,
                 Floating, GpioExt, Input, OpenDrain, Output,
-                PullDown, PullUp, PushPull, State,
+                Pull, PullDown, PullUp, PushPull, State,
             };
 
             /// GPIO parts
@@ -238,6 +250,29 @@ macro_rules! gpio {
                 }
 
                 impl<MODE> $PXi<MODE> {
+                    /// Configures the pin to serve as alternate function 0 (AF0)
+                    pub fn into_af0(
+                        self,
+                        moder: &mut MODER,
+                        afr: &mut $AFR,
+                    ) -> $PXi<Alternate<AF0, MODE>> {
+                        let offset = 2 * $i;
+
+                        // alternate function mode
+                        let mode = 0b10;
+                        moder.moder().modify(|r, w| unsafe {
+                            w.bits((r.bits() & !(0b11 << offset)) | (mode << offset))
+                        });
+
+                        let af = 0;
+                        let offset = 4 * ($i % 8);
+                        afr.afr().modify(|r, w| unsafe {
+                            w.bits((r.bits() & !(0b1111 << offset)) | (af << offset))
+                        });
+
+                        $PXi { _mode: PhantomData }
+                    }
+
                     /// Configures the pin to serve as alternate function 1 (AF1)
                     pub fn into_af1(
                         self,
@@ -426,6 +461,46 @@ macro_rules! gpio {
                         $PXi { _mode: PhantomData }
                     }
 
+                    /// Sets the pull applied to the pin in Standby and Shutdown (PWR_PUCRx, PWR_PDCRx)
+                    ///
+                    /// The pull only takes effect once enabled by `Pwr::apply_pull_configuration`.
+                    pub fn set_standby_pull(&mut self, _pwr: &mut Pwr, pull: Pull) {
+                        let (up, down) = match pull {
+                            Pull::None => (false, false),
+                            Pull::Up => (true, false),
+                            Pull::Down => (false, true),
+                        };
+                        // NOTE(unsafe) `Pwr` grants exclusive access to the PWR registers
+                        let pwr = unsafe { &*PWR::ptr() };
+                        pwr.$pucrx.modify(|r, w| unsafe {
+                            w.bits((r.bits() & !(1 << $i)) | (u32::from(up) << $i))
+                        });
+                        pwr.$pdcrx.modify(|r, w| unsafe {
+                            w.bits((r.bits() & !(1 << $i)) | (u32::from(down) << $i))
+                        });
+                    }
+
+                    /// Configures the pin to operate in analog mode
+                    pub fn into_analog(
+                        self,
+                        moder: &mut MODER,
+                        pupdr: &mut PUPDR,
+                    ) -> $PXi<Analog> {
+                        let offset = 2 * $i;
+
+                        // analog mode
+                        moder
+                            .moder()
+                            .modify(|r, w| unsafe { w.bits(r.bits() | (0b11 << offset)) });
+
+                        // no pull-up or pull-down
+                        pupdr
+                            .pupdr()
+                            .modify(|r, w| unsafe { w.bits(r.bits() & !(0b11 << offset)) });
+
+                        $PXi { _mode: PhantomData }
+                    }
+
                     /// Configures the pin to operate as a floating input pin
                     pub fn into_floating_input(
                         self,
@@ -635,7 +710,7 @@ macro_rules! gpio {
     }
 }
 
-gpio!(GPIOA, gpioa, gpioa, gpioaen, gpioarst, PAx, [
+gpio!(GPIOA, gpioa, gpioa, gpioaen, gpioarst, pucra, pdcra, PAx, [
     PA0: (pa0, 0, Input<Floating>, AFRL),
     PA1: (pa1, 1, Input<Floating>, AFRL),
     PA2: (pa2, 2, Input<Floating>, AFRL),
@@ -654,7 +729,7 @@ gpio!(GPIOA, gpioa, gpioa, gpioaen, gpioarst, PAx, [
     PA15: (pa15, 15, Input<Floating>, AFRH),
 ]);
 
-gpio!(GPIOB, gpiob, gpiob, gpioben, gpiobrst, PBx, [
+gpio!(GPIOB, gpiob, gpiob, gpioben, gpiobrst, pucrb, pdcrb, PBx, [
     PB0: (pb0, 0, Input<Floating>, AFRL),
     PB1: (pb1, 1, Input<Floating>, AFRL),
     PB2: (pb2, 2, Input<Floating>, AFRL),
@@ -673,7 +748,7 @@ gpio!(GPIOB, gpiob, gpiob, gpioben, gpiobrst, PBx, [
     PB15: (pb15, 15, Input<Floating>, AFRH),
 ]);
 
-gpio!(GPIOC, gpioc, gpioc, gpiocen, gpiocrst, PCx, [
+gpio!(GPIOC, gpioc, gpioc, gpiocen, gpiocrst, pucrc, pdcrc, PCx, [
     PC0: (pc0, 0, Input<Floating>, AFRL),
     PC1: (pc1, 1, Input<Floating>, AFRL),
     PC2: (pc2, 2, Input<Floating>, AFRL),
@@ -692,7 +767,7 @@ gpio!(GPIOC, gpioc, gpioc, gpiocen, gpiocrst, PCx, [
     PC15: (pc15, 15, Input<Floating>, AFRH),
 ]);
 
-gpio!(GPIOD, gpiod, gpioc, gpioden, gpiodrst, PDx, [
+gpio!(GPIOD, gpiod, gpioc, gpioden, gpiodrst, pucrd, pdcrd, PDx, [
     PD0: (pd0, 0, Input<Floating>, AFRL),
     PD1: (pd1, 1, Input<Floating>, AFRL),
     PD2: (pd2, 2, Input<Floating>, AFRL),
@@ -711,7 +786,7 @@ gpio!(GPIOD, gpiod, gpioc, gpioden, gpiodrst, PDx, [
     PD15: (pd15, 15, Input<Floating>, AFRH),
 ]);
 
-gpio!(GPIOE, gpioe, gpioc, gpioeen, gpioerst, PEx, [
+gpio!(GPIOE, gpioe, gpioc, gpioeen, gpioerst, pucre, pdcre, PEx, [
     PE0: (pe0, 0, Input<Floating>, AFRL),
     PE1: (pe1, 1, Input<Floating>, AFRL),
     PE2: (pe2, 2, Input<Floating>, AFRL),
@@ -69,7 +69,7 @@ macro_rules! busy_wait {
 }
 
 macro_rules! hal {
-    ($($I2CX:ident: ($i2cX:ident, $i2cXen:ident, $i2cXrst:ident),)+) => {
+    ($($I2CX:ident: ($i2cX:ident, $i2cXen:ident, $i2cXrst:ident, $i2cXclk:ident),)+) => {
         $(
             impl<SCL, SDA> I2c<$I2CX, (SCL, SDA)> {
                 /// Configures the I2C peripheral to work in master mode
@@ -93,14 +93,14 @@ macro_rules! hal {
                     assert!(freq <= 1_000_000);
 
                     // TODO review compliance with the timing requirements of I2C
-                    // t_I2CCLK = 1 / PCLK1
+                    // t_I2CCLK = 1 / I2CCLK (kernel clock, PCLK1 by default)
                     // t_PRESC  = (PRESC + 1) * t_I2CCLK
                     // t_SCLL   = (SCLL + 1) * t_PRESC
                     // t_SCLH   = (SCLH + 1) * t_PRESC
                     //
                     // t_SYNC1 + t_SYNC2 > 4 * t_I2CCLK
                     // t_SCL ~= t_SYNC1 + t_SYNC2 + t_SCLL + t_SCLH
-                    let i2cclk = clocks.pclk1().0;
+                    let i2cclk = clocks.$i2cXclk().0;
                     let ratio = i2cclk / freq - 4;
                     let (presc, scll, sclh, sdadel, scldel) = if freq >= 100_000 {
                         // fast-mode or fast-mode plus
@@ -313,6 +313,6 @@ macro_rules! hal {
 }
 
 hal! {
-    I2C1: (i2c1, i2c1en, i2c1rst),
-    I2C2: (i2c2, i2c2en, i2c2rst),
+    I2C1: (i2c1, i2c1en, i2c1rst, i2c1clk),
+    I2C2: (i2c2, i2c2en, i2c2rst, i2c2clk),
 }
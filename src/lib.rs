@@ -56,6 +56,14 @@ pub use crate::pac as stm32;
 pub mod prelude;
 
 
+#[cfg(any(
+    feature = "stm32l4x1",
+    feature = "stm32l4x2",
+    feature = "stm32l4x3",
+    feature = "stm32l4x5",
+    feature = "stm32l4x6"
+))]
+pub mod adc;
 pub mod datetime;
 #[cfg(any(
     feature = "stm32l4x1",
@@ -104,6 +112,14 @@ pub mod i2c;
     feature = "stm32l4x5",
     feature = "stm32l4x6"
 ))]
+pub mod mco;
+#[cfg(any(
+    feature = "stm32l4x1",
+    feature = "stm32l4x2",
+    feature = "stm32l4x3",
+    feature = "stm32l4x5",
+    feature = "stm32l4x6"
+))]
 pub mod pwr;
 #[cfg(any(
     feature = "stm32l4x1",
@@ -152,6 +168,14 @@ pub mod spi;
     feature = "stm32l4x5",
     feature = "stm32l4x6"
 ))]
+pub mod sram2;
+#[cfg(any(
+    feature = "stm32l4x1",
+    feature = "stm32l4x2",
+    feature = "stm32l4x3",
+    feature = "stm32l4x5",
+    feature = "stm32l4x6"
+))]
 pub mod time;
 #[cfg(any(
     feature = "stm32l4x1",
@@ -176,4 +200,12 @@ pub mod tsc;
     feature = "stm32l4x5",
     feature = "stm32l4x6"
 ))]
+pub mod watchdog;
+#[cfg(any(
+    feature = "stm32l4x1",
+    feature = "stm32l4x2",
+    feature = "stm32l4x3",
+    feature = "stm32l4x5",
+    feature = "stm32l4x6"
+))]
 pub mod pwm;
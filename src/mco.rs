@@ -0,0 +1,170 @@
+//! Microcontroller clock output (MCO) and low-speed clock output (LSCO)
+//!
+//! `Mco` routes one of the internal clocks, divided by a power of two, to PA8.
+//! `Lsco` routes LSI or LSE to PA2; unlike MCO it keeps running in Stop and
+//! Standby modes.
+
+use crate::gpio::gpioa::{PA2, PA8};
+use crate::gpio::{Alternate, Analog, Output, PushPull, AF0};
+use crate::pwr;
+use crate::rcc::{Clocks, ClockError, Source, SysClkSource, BDCR, CFGR};
+use crate::stm32::RCC;
+use crate::time::Hertz;
+
+/// Clock routed to the MCO pin (RCC_CFGR MCOSEL)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum McoSource {
+    SYSCLK = 0b0001,
+    MSI = 0b0010,
+    HSI16 = 0b0011,
+    HSE = 0b0100,
+    /// Main PLL output (PLLCLK)
+    PLL = 0b0101,
+    LSI = 0b0110,
+    LSE = 0b0111,
+    HSI48 = 0b1000,
+}
+
+/// Division factor applied to the MCO source (RCC_CFGR MCOPRE)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum McoPrescaler {
+    Div1 = 0b000,
+    Div2 = 0b001,
+    Div4 = 0b010,
+    Div8 = 0b011,
+    Div16 = 0b100,
+}
+
+/// Clock routed to the LSCO pin (RCC_BDCR LSCOSEL)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LscoSource {
+    LSI,
+    LSE,
+}
+
+/// Microcontroller clock output on PA8
+pub struct Mco {
+    pin: PA8<Alternate<AF0, Output<PushPull>>>,
+    freq: Hertz,
+}
+
+impl Mco {
+    /// Starts outputting `source` divided by `prescaler` on PA8
+    ///
+    /// Fails if `source` is not running according to `clocks`. HSI16 is
+    /// switched on if needed; the main PLL is only available while it drives
+    /// SYSCLK.
+    pub fn new(
+        pin: PA8<Alternate<AF0, Output<PushPull>>>,
+        source: McoSource,
+        prescaler: McoPrescaler,
+        cfgr: &mut CFGR,
+        clocks: &Clocks,
+    ) -> Result<Self, ClockError> {
+        // need to steal access to RCC, more precisely,
+        // RCC.CR.HSION, which is morally ours
+        let rcc = unsafe { &*RCC::ptr() };
+
+        let input = match source {
+            McoSource::SYSCLK => clocks.sysclk(),
+            McoSource::MSI => clocks.msi().ok_or(ClockError::SourceDisabled(Source::MSI))?.to_hertz(),
+            McoSource::HSI16 => {
+                rcc.cr.modify(|_, w| w.hsion().set_bit());
+                crate::rcc::wait_for(
+                    || rcc.cr.read().hsirdy().bit_is_set(),
+                    ClockError::OscillatorTimeout(Source::HSI16),
+                    crate::rcc::READY_TIMEOUT,
+                )?;
+                Hertz(16_000_000)
+            }
+            McoSource::HSE => clocks.hse().ok_or(ClockError::SourceDisabled(Source::HSE))?,
+            McoSource::PLL => {
+                if clocks.sysclk_source() != SysClkSource::PLL {
+                    return Err(ClockError::SourceDisabled(Source::PLL));
+                }
+                clocks.sysclk()
+            }
+            McoSource::LSI => {
+                if !clocks.lsi() {
+                    return Err(ClockError::SourceDisabled(Source::LSI));
+                }
+                Hertz(32_000)
+            }
+            McoSource::LSE => {
+                if !clocks.lse() {
+                    return Err(ClockError::SourceDisabled(Source::LSE));
+                }
+                Hertz(32_768)
+            }
+            McoSource::HSI48 => {
+                if !clocks.hsi48() {
+                    return Err(ClockError::SourceDisabled(Source::HSI48));
+                }
+                Hertz(48_000_000)
+            }
+        };
+
+        cfgr.cfgr()
+            .modify(|_, w| unsafe { w.mcosel().bits(source as u8).mcopre().bits(prescaler as u8) });
+
+        Ok(Mco {
+            pin,
+            freq: Hertz(input.0 >> prescaler as u32),
+        })
+    }
+
+    /// Returns the nominal frequency on the pin
+    pub fn freq(&self) -> Hertz {
+        self.freq
+    }
+
+    /// Stops the clock output and releases the pin
+    pub fn free(self, cfgr: &mut CFGR) -> PA8<Alternate<AF0, Output<PushPull>>> {
+        cfgr.cfgr()
+            .modify(|_, w| unsafe { w.mcosel().bits(0b0000).mcopre().bits(0b000) });
+        self.pin
+    }
+}
+
+/// Low-speed clock output on PA2
+pub struct Lsco {
+    pin: PA2<Analog>,
+}
+
+impl Lsco {
+    /// Starts outputting `source` on PA2
+    ///
+    /// The LSCO configuration lives in the backup domain, so write access to
+    /// it is enabled through `pwrcr1`, as done by `Rtc::rtc`.
+    pub fn new(
+        pin: PA2<Analog>,
+        source: LscoSource,
+        bdcr: &mut BDCR,
+        pwrcr1: &mut pwr::CR1,
+        clocks: &Clocks,
+    ) -> Result<Self, ClockError> {
+        match source {
+            LscoSource::LSI if !clocks.lsi() => return Err(ClockError::SourceDisabled(Source::LSI)),
+            LscoSource::LSE if !clocks.lse() => return Err(ClockError::SourceDisabled(Source::LSE)),
+            _ => {}
+        }
+
+        pwrcr1.reg().modify(|_, w| w.dbp().set_bit());
+        while pwrcr1.reg().read().dbp().bit_is_clear() {}
+
+        bdcr.enr().modify(|_, w| {
+            w.lscosel()
+                .bit(source == LscoSource::LSE)
+                .lscoen()
+                .set_bit()
+        });
+
+        Ok(Lsco { pin })
+    }
+
+    /// Stops the clock output and releases the pin
+    pub fn free(self, bdcr: &mut BDCR) -> PA2<Analog> {
+        bdcr.enr().modify(|_, w| w.lscoen().clear_bit());
+        self.pin
+    }
+}
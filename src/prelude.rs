@@ -13,6 +13,4 @@ pub use crate::pwr::PwrExt as _stm32l4_hal_PwrExt;
 pub use crate::pwm::PwmExt as _stm32l4_hal_PwmExt;
 
 #[cfg(feature = "extra-traits")]
-pub use crate::hal::flash::{Locking, Read, WriteErase};  // LockingImpl not exposed on purpose
-#[cfg(feature = "extra-traits")]
-pub use crate::flash::OptionBytesLocking;
+pub use crate::hal::flash::{Read, WriteErase};
@@ -124,7 +124,7 @@ pub struct C3;
 pub struct C4;
 
 macro_rules! hal {
-    ($($TIMX:ident: ($timX:ident, $timXen:ident, $timXrst:ident, $apb:ident),)+) => {
+    ($($TIMX:ident: ($timX:ident, $timXen:ident, $timXrst:ident, $apb:ident, $timclk:ident),)+) => {
         $(
             fn $timX<PINS>(
                 tim: $TIMX,
@@ -159,7 +159,7 @@ macro_rules! hal {
                     tim.ccmr2_output
                         .modify(|_, w| unsafe { w.oc4pe().set_bit().oc4m().bits(6) });
                 }
-                let clk = clocks.pclk1().0;
+                let clk = clocks.$timclk().0;
                 let freq = freq.0;
                 let ticks = clk / freq;
 
@@ -280,5 +280,5 @@ macro_rules! hal {
 }
 
 hal! {
-    TIM2: (tim2, tim2en, tim2rst, APB1R1),
+    TIM2: (tim2, tim2en, tim2rst, APB1R1, timclk1),
 }
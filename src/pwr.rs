@@ -1,7 +1,13 @@
 //! Power management
 
-use crate::rcc::{APB1R1};
-use crate::stm32::{pwr, PWR};
+use cortex_m::asm;
+use cortex_m::peripheral::SCB;
+
+use crate::gpio::gpioa::{PA0, PA2};
+use crate::gpio::gpioc::{PC13, PC5};
+use crate::gpio::gpioe::PE6;
+use crate::rcc::{self, APB1R1, ClockError, Clocks, Source, SysClkSource};
+use crate::stm32::{pwr, EXTI, PWR};
 
 
 pub struct Pwr {
@@ -30,6 +36,58 @@ impl PwrExt for PWR {
     }
 }
 
+/// Instruction used to enter a low-power mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Entry {
+    /// Wait for interrupt
+    Wfi,
+    /// Wait for event
+    Wfe,
+}
+
+/// Low-power mode selection (PWR_CR1 LPMS)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LowPowerMode {
+    Stop0 = 0b000,
+    Stop1 = 0b001,
+    Stop2 = 0b010,
+    Standby = 0b011,
+    Shutdown = 0b100,
+}
+
+/// Dynamic voltage scaling range of the main regulator (PWR_CR1 VOS)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VoltageRange {
+    /// High performance, 1.2 V core, SYSCLK up to 80 MHz
+    Range1 = 0b01,
+    /// Low power, 1.0 V core, SYSCLK up to 26 MHz
+    Range2 = 0b10,
+}
+
+/// Returns the active voltage range
+pub(crate) fn voltage_range() -> VoltageRange {
+    match unsafe { (*PWR::ptr()).cr1.read().vos().bits() } {
+        0b10 => VoltageRange::Range2,
+        _ => VoltageRange::Range1,
+    }
+}
+
+/// Switches the voltage range and waits for the regulator to settle
+///
+/// The PWR clock must be enabled.
+pub(crate) fn set_voltage_range(range: VoltageRange) -> Result<(), ClockError> {
+    let pwr = unsafe { &*PWR::ptr() };
+    pwr.cr1.modify(|_, w| unsafe { w.vos().bits(range as u8) });
+    rcc::wait_for(
+        || pwr.sr2.read().vosf().bit_is_clear(),
+        ClockError::VoltageScalingTimeout,
+        rcc::READY_TIMEOUT,
+    )
+}
+
+/// Highest SYSCLK allowed in low-power run mode
+const LPR_SYSCLK_MAX: u32 = 2_000_000;
+
 impl Pwr {
     pub fn enable_usb(&self) {
         // TODO:
@@ -38,6 +96,420 @@ impl Pwr {
         // - etc.
         unsafe { (*PWR::ptr()).cr2.modify(|_, w| w.usv().set_bit()) }
     }
+
+    /// Returns the active voltage range
+    pub fn voltage_range(&self) -> VoltageRange {
+        voltage_range()
+    }
+
+    /// Switches the voltage range, waiting until the regulator is ready (VOSF)
+    ///
+    /// Before lowering to Range 2 the clocks and flash wait states must already
+    /// be within its limits. `rcc::CFGR::voltage_range` takes care of that
+    /// ordering and is the preferred way to change the range.
+    pub fn set_voltage_range(&mut self, range: VoltageRange) -> Result<(), ClockError> {
+        set_voltage_range(range)
+    }
+
+    /// Enables the programmable voltage detector (PVD) at `level`
+    pub fn enable_pvd(&mut self, level: PvdLevel) {
+        self.cr2.reg().modify(|_, w| unsafe { w.pls().bits(level as u8) });
+        self.cr2.reg().modify(|_, w| w.pvde().set_bit());
+    }
+
+    /// Disables the programmable voltage detector
+    pub fn disable_pvd(&mut self) {
+        self.cr2.reg().modify(|_, w| w.pvde().clear_bit());
+    }
+
+    /// Returns whether VDD is below the PVD threshold (PVDO)
+    pub fn is_vdd_low(&self) -> bool {
+        unsafe { (*PWR::ptr()).sr2.read().pvdo().bit_is_set() }
+    }
+
+    /// Raises the PVD_PVM interrupt on EXTI line 16 when VDD crosses the PVD threshold
+    pub fn listen_pvd(&mut self, exti: &mut EXTI, crossing: Crossing) {
+        let mask = 1 << PVD_EXTI_LINE;
+        let (rising, falling) = crossing.edges();
+        exti.rtsr1.modify(|r, w| unsafe { w.bits(with_bit(r.bits(), mask, rising)) });
+        exti.ftsr1.modify(|r, w| unsafe { w.bits(with_bit(r.bits(), mask, falling)) });
+        exti.imr1.modify(|r, w| unsafe { w.bits(r.bits() | mask) });
+    }
+
+    /// Stops raising the interrupt on EXTI line 16
+    pub fn unlisten_pvd(&mut self, exti: &mut EXTI) {
+        exti.imr1.modify(|r, w| unsafe { w.bits(r.bits() & !(1 << PVD_EXTI_LINE)) });
+    }
+
+    /// Clears the pending PVD interrupt on EXTI line 16
+    pub fn clear_pvd_interrupt(&mut self, exti: &mut EXTI) {
+        exti.pr1.write(|w| unsafe { w.bits(1 << PVD_EXTI_LINE) });
+    }
+
+    /// Enables the peripheral voltage monitor `pvm`
+    ///
+    /// The monitored supply must be present on the device, e.g. VDDIO2 only
+    /// exists on packages with port G.
+    pub fn enable_pvm(&mut self, pvm: Pvm) {
+        self.cr2
+            .reg()
+            .modify(|r, w| unsafe { w.bits(r.bits() | (1 << (pvm as u8 + 4))) });
+    }
+
+    /// Disables the peripheral voltage monitor `pvm`
+    pub fn disable_pvm(&mut self, pvm: Pvm) {
+        self.cr2
+            .reg()
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << (pvm as u8 + 4))) });
+    }
+
+    /// Returns whether the supply monitored by `pvm` is below its threshold (PVMOx)
+    pub fn is_pvm_low(&self, pvm: Pvm) -> bool {
+        unsafe { (*PWR::ptr()).sr2.read().bits() & (1 << (pvm as u8 + 12)) != 0 }
+    }
+
+    /// Raises the PVD_PVM interrupt when the monitored supply crosses the threshold of `pvm`
+    ///
+    /// PVM1 to PVM4 are EXTI lines 35 to 38.
+    pub fn listen_pvm(&mut self, pvm: Pvm, exti: &mut EXTI, crossing: Crossing) {
+        let mask = 1 << (pvm.exti_line() - 32);
+        let (rising, falling) = crossing.edges();
+        exti.rtsr2.modify(|r, w| unsafe { w.bits(with_bit(r.bits(), mask, rising)) });
+        exti.ftsr2.modify(|r, w| unsafe { w.bits(with_bit(r.bits(), mask, falling)) });
+        exti.imr2.modify(|r, w| unsafe { w.bits(r.bits() | mask) });
+    }
+
+    /// Stops raising the interrupt of `pvm`
+    pub fn unlisten_pvm(&mut self, pvm: Pvm, exti: &mut EXTI) {
+        let mask = 1 << (pvm.exti_line() - 32);
+        exti.imr2.modify(|r, w| unsafe { w.bits(r.bits() & !mask) });
+    }
+
+    /// Clears the pending interrupt of `pvm`
+    pub fn clear_pvm_interrupt(&mut self, pvm: Pvm, exti: &mut EXTI) {
+        exti.pr2.write(|w| unsafe { w.bits(1 << (pvm.exti_line() - 32)) });
+    }
+
+    /// Applies the pulls set by `set_standby_pull` on the GPIO pins in Standby
+    /// and Shutdown modes (APC)
+    ///
+    /// Otherwise all pins float while the core domain is off.
+    pub fn apply_pull_configuration(&mut self, on: bool) {
+        self.cr3.reg().modify(|_, w| w.apc().bit(on));
+    }
+
+    /// Charges the battery or supercapacitor on VBAT from VDD through `resistor` (VBE, VBRS)
+    ///
+    /// Charging stops automatically in VBAT mode.
+    pub fn enable_vbat_charging(&mut self, resistor: ChargeResistor) {
+        self.cr4
+            .reg()
+            .modify(|_, w| w.vbrs().bit(resistor == ChargeResistor::R1k5));
+        self.cr4.reg().modify(|_, w| w.vbe().set_bit());
+    }
+
+    /// Stops charging VBAT
+    pub fn disable_vbat_charging(&mut self) {
+        self.cr4.reg().modify(|_, w| w.vbe().clear_bit());
+    }
+
+    /// Enters Sleep mode: the core stops, peripherals keep running
+    pub fn enter_sleep(&mut self, scb: &mut SCB, entry: Entry) {
+        scb.clear_sleepdeep();
+        wait(entry);
+    }
+
+    /// Switches the main regulator to low-power run mode (LPR)
+    ///
+    /// SYSCLK must run directly from MSI at `RANGE2M` or below, e.g. after
+    /// `rcc::reconfigure(&CFGR::default().msi(MsiFreq::RANGE2M).sysclk(2.mhz()))`.
+    pub fn enter_low_power_run(&mut self, clocks: &Clocks) -> Result<(), ClockError> {
+        if clocks.sysclk_source() != SysClkSource::MSI {
+            return Err(ClockError::SourceDisabled(Source::MSI));
+        }
+        match clocks.msi() {
+            // i.e. `RANGE2M` or below
+            Some(msi) if msi.to_hertz().0 <= LPR_SYSCLK_MAX => {}
+            _ => return Err(ClockError::SysclkTooHigh),
+        }
+        self.cr1.reg().modify(|_, w| w.lpr().set_bit());
+        Ok(())
+    }
+
+    /// Returns to the main regulator, after which the clocks may be raised again
+    ///
+    /// Fails with `ClockError::VoltageScalingTimeout` if the main regulator
+    /// does not take over in time.
+    pub fn exit_low_power_run(&mut self) -> Result<(), ClockError> {
+        self.cr1.reg().modify(|_, w| w.lpr().clear_bit());
+        let pwr = unsafe { &*PWR::ptr() };
+        rcc::wait_for(
+            || pwr.sr2.read().reglpf().bit_is_clear(),
+            ClockError::VoltageScalingTimeout,
+            rcc::READY_TIMEOUT,
+        )
+    }
+
+    /// Enters Stop 0 mode, with the main regulator on
+    ///
+    /// On wake-up SYSCLK runs from MSI or HSI16 (RCC_CFGR STOPWUCK) and the
+    /// clocks have to be restored, e.g. with `rcc::reconfigure`.
+    pub fn enter_stop0(&mut self, scb: &mut SCB, entry: Entry) {
+        self.enter_deep(scb, LowPowerMode::Stop0, entry);
+    }
+
+    /// Enters Stop 1 mode, with the low-power regulator
+    ///
+    /// Clocks have to be restored on wake-up, see `enter_stop0`.
+    pub fn enter_stop1(&mut self, scb: &mut SCB, entry: Entry) {
+        self.enter_deep(scb, LowPowerMode::Stop1, entry);
+    }
+
+    /// Enters Stop 2 mode, the lowest power mode keeping SRAM and registers
+    ///
+    /// Most peripherals are powered off except LPUART1, LPTIM1, I2C3 and RTC.
+    /// Clocks have to be restored on wake-up, see `enter_stop0`.
+    pub fn enter_stop2(&mut self, scb: &mut SCB, entry: Entry) {
+        self.enter_deep(scb, LowPowerMode::Stop2, entry);
+    }
+
+    /// Enters Standby mode
+    ///
+    /// The core domain is powered off, leaving it resets the device. Only the
+    /// backup domain, and optionally SRAM2, are retained.
+    pub fn enter_standby(&mut self, scb: &mut SCB, entry: Entry) -> ! {
+        self.clear_wakeup_flags();
+        self.enter_deep(scb, LowPowerMode::Standby, entry);
+        // a pending wake-up event would only leave a few instructions
+        loop {
+            wait(entry);
+        }
+    }
+
+    /// Enters Shutdown mode, the lowest power mode
+    ///
+    /// Like Standby, but without brown-out reset, LSI or SRAM2 retention.
+    pub fn enter_shutdown(&mut self, scb: &mut SCB, entry: Entry) -> ! {
+        self.clear_wakeup_flags();
+        self.enter_deep(scb, LowPowerMode::Shutdown, entry);
+        loop {
+            wait(entry);
+        }
+    }
+
+    fn enter_deep(&mut self, scb: &mut SCB, mode: LowPowerMode, entry: Entry) {
+        self.cr1.reg().modify(|_, w| unsafe { w.lpms().bits(mode as u8) });
+        scb.set_sleepdeep();
+        wait(entry);
+        // plain WFI/WFE enter Sleep mode again
+        scb.clear_sleepdeep();
+    }
+
+    /// Returns what woke the device up, read this before `clear_wakeup_flags`
+    pub fn wakeup_reason(&self) -> WakeupReason {
+        let sr1 = unsafe { (*PWR::ptr()).sr1.read() };
+        WakeupReason {
+            standby: sr1.sbf().bit_is_set(),
+            wkup: [
+                sr1.wuf1().bit_is_set(),
+                sr1.wuf2().bit_is_set(),
+                sr1.wuf3().bit_is_set(),
+                sr1.wuf4().bit_is_set(),
+                sr1.wuf5().bit_is_set(),
+            ],
+            internal: sr1.wufi().bit_is_set(),
+        }
+    }
+
+    /// Clears the wake-up pin flags and the Standby flag
+    ///
+    /// Entering Standby or Shutdown with a wake-up flag set leaves it
+    /// immediately, which is why `enter_standby` and `enter_shutdown` call this.
+    pub fn clear_wakeup_flags(&mut self) {
+        let pwr = unsafe { &*PWR::ptr() };
+        pwr.scr.write(|w| {
+            w.csbf()
+                .set_bit()
+                .cwuf1()
+                .set_bit()
+                .cwuf2()
+                .set_bit()
+                .cwuf3()
+                .set_bit()
+                .cwuf4()
+                .set_bit()
+                .cwuf5()
+                .set_bit()
+        });
+    }
+}
+
+/// VBAT charging resistor (PWR_CR4 VBRS)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChargeResistor {
+    /// 5 kOhm
+    R5k,
+    /// 1.5 kOhm
+    R1k5,
+}
+
+/// Falling threshold of the programmable voltage detector (PWR_CR2 PLS)
+///
+/// The rising threshold is about 100 mV higher.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PvdLevel {
+    V2_0 = 0b000,
+    V2_2 = 0b001,
+    V2_4 = 0b010,
+    V2_5 = 0b011,
+    V2_6 = 0b100,
+    V2_8 = 0b101,
+    V2_9 = 0b110,
+    /// PVD_IN pin (PB7) compared to VREFINT
+    External = 0b111,
+}
+
+/// Peripheral voltage monitor and its fixed threshold (PWR_CR2 PVMEx)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pvm {
+    /// VDDUSB below 1.2 V
+    Pvm1Vddusb = 0,
+    /// VDDIO2 below 0.9 V
+    Pvm2Vddio2 = 1,
+    /// VDDA below 1.62 V
+    Pvm3Vdda = 2,
+    /// VDDA below 2.2 V
+    Pvm4Vdda = 3,
+}
+
+impl Pvm {
+    fn exti_line(self) -> u8 {
+        35 + self as u8
+    }
+}
+
+/// Threshold crossing raising a PVD or PVM interrupt
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Crossing {
+    /// The supply drops below the threshold
+    Below,
+    /// The supply rises above the threshold
+    Above,
+    Both,
+}
+
+const PVD_EXTI_LINE: u8 = 16;
+
+impl Crossing {
+    /// Rising and falling edge triggers of the EXTI line
+    ///
+    /// The PVDO and PVMOx outputs are high while the supply is low, so a drop
+    /// below the threshold is a rising edge.
+    fn edges(self) -> (bool, bool) {
+        (self != Crossing::Above, self != Crossing::Below)
+    }
+}
+
+fn with_bit(bits: u32, mask: u32, on: bool) -> u32 {
+    if on {
+        bits | mask
+    } else {
+        bits & !mask
+    }
+}
+
+/// Source of the last wake-up, see `Pwr::wakeup_reason`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WakeupReason {
+    /// The device was in Standby mode (SBF)
+    pub standby: bool,
+    /// Wake-up pin flags, `wkup[0]` is WKUP1 (WUF1-WUF5)
+    pub wkup: [bool; 5],
+    /// An internal wake-up source, e.g. the RTC, fired (WUFI)
+    pub internal: bool,
+}
+
+/// Active edge of a wake-up pin (PWR_CR4 WPx)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    Rising,
+    Falling,
+}
+
+/// Pins able to wake the device up from Standby and Shutdown
+pub trait WakeupSource {
+    /// Bit of the pin in PWR_CR3 EWUPx, PWR_CR4 WPx and PWR_SR1 WUFx
+    const INDEX: u8;
+}
+impl<MODE> WakeupSource for PA0<MODE> {
+    const INDEX: u8 = 0;
+}
+impl<MODE> WakeupSource for PC13<MODE> {
+    const INDEX: u8 = 1;
+}
+impl<MODE> WakeupSource for PE6<MODE> {
+    const INDEX: u8 = 2;
+}
+impl<MODE> WakeupSource for PA2<MODE> {
+    const INDEX: u8 = 3;
+}
+impl<MODE> WakeupSource for PC5<MODE> {
+    const INDEX: u8 = 4;
+}
+
+/// A pin enabled as a wake-up source (WKUP1-WKUP5)
+pub struct WakeupPin<PIN> {
+    pin: PIN,
+}
+
+impl<PIN> WakeupPin<PIN>
+where
+    PIN: WakeupSource,
+{
+    /// Enables `pin` to wake the device up on `edge`
+    pub fn new(pin: PIN, edge: Edge, cr3: &mut CR3, cr4: &mut CR4) -> Self {
+        let bit = 1 << PIN::INDEX;
+
+        // the polarity must be set before enabling the pin, otherwise the
+        // change may be detected as an edge
+        cr4.reg().modify(|r, w| unsafe {
+            match edge {
+                Edge::Rising => w.bits(r.bits() & !bit),
+                Edge::Falling => w.bits(r.bits() | bit),
+            }
+        });
+        cr3.reg().modify(|r, w| unsafe { w.bits(r.bits() | bit) });
+
+        let mut wakeup = WakeupPin { pin };
+        wakeup.clear_flag();
+        wakeup
+    }
+
+    /// Returns whether this pin caused a wake-up (WUFx)
+    pub fn is_flagged(&self) -> bool {
+        unsafe { (*PWR::ptr()).sr1.read().bits() & (1 << PIN::INDEX) != 0 }
+    }
+
+    /// Clears the wake-up flag of this pin (CWUFx)
+    pub fn clear_flag(&mut self) {
+        unsafe { (*PWR::ptr()).scr.write(|w| w.bits(1 << PIN::INDEX)) }
+    }
+
+    /// Disables the wake-up function and releases the pin
+    pub fn free(self, cr3: &mut CR3) -> PIN {
+        cr3.reg()
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << PIN::INDEX)) });
+        self.pin
+    }
+}
+
+fn wait(entry: Entry) {
+    // all memory accesses have to complete before the core stops
+    asm::dsb();
+    match entry {
+        Entry::Wfi => asm::wfi(),
+        Entry::Wfe => asm::wfe(),
+    }
 }
 
 /// CR1
@@ -46,8 +518,6 @@ pub struct CR1 {
 }
 
 impl CR1 {
-    // TODO remove `allow`
-    #[allow(dead_code)]
     pub(crate) fn reg(&mut self) -> &pwr::CR1 {
         // NOTE(unsafe) this proxy grants exclusive access to this register
         unsafe { &(*PWR::ptr()).cr1 }
@@ -60,8 +530,6 @@ pub struct CR2 {
 }
 
 impl CR2 {
-    // TODO remove `allow`
-    #[allow(dead_code)]
     pub(crate) fn reg(&mut self) -> &pwr::CR2 {
         // NOTE(unsafe) this proxy grants exclusive access to this register
         unsafe { &(*PWR::ptr()).cr2 }
@@ -73,8 +541,6 @@ pub struct CR3 {
 }
 
 impl CR3 {
-    // TODO remove `allow`
-    #[allow(dead_code)]
     pub(crate) fn reg(&mut self) -> &pwr::CR3 {
         // NOTE(unsafe) this proxy grants exclusive access to this register
         unsafe { &(*PWR::ptr()).cr3 }
@@ -86,8 +552,6 @@ pub struct CR4 {
 }
 
 impl CR4 {
-    // TODO remove `allow`
-    #[allow(dead_code)]
     pub(crate) fn reg(&mut self) -> &pwr::CR4 {
         // NOTE(unsafe) this proxy grants exclusive access to this register
         unsafe { &(*PWR::ptr()).cr4 }
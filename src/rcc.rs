@@ -1,10 +1,9 @@
 //! Reset and Clock Control
 
-use core::cmp;
-
 use cast::u32;
 use crate::stm32::{rcc, RCC};
-use crate::stm32::FLASH;
+use crate::stm32::{FLASH, PWR};
+use crate::pwr::{self, VoltageRange};
 
 // use crate::flash::ACR;
 use crate::time::Hertz;
@@ -37,6 +36,87 @@ pub enum MsiFreq {
     RANGE48M = 11,
 }
 
+impl MsiFreq {
+    /// Returns the nominal frequency of this MSI range
+    pub fn to_hertz(self) -> Hertz {
+        Hertz(match self {
+            MsiFreq::RANGE100K => 100_000,
+            MsiFreq::RANGE200K => 200_000,
+            MsiFreq::RANGE400K => 400_000,
+            MsiFreq::RANGE800K => 800_000,
+            MsiFreq::RANGE1M => 1_000_000,
+            MsiFreq::RANGE2M => 2_000_000,
+            MsiFreq::RANGE4M => 4_000_000,
+            MsiFreq::RANGE8M => 8_000_000,
+            MsiFreq::RANGE16M => 16_000_000,
+            MsiFreq::RANGE24M => 24_000_000,
+            MsiFreq::RANGE32M => 32_000_000,
+            MsiFreq::RANGE48M => 48_000_000,
+        })
+    }
+}
+
+/// HSE operating mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HseMode {
+    /// Crystal or ceramic resonator connected between OSC_IN and OSC_OUT
+    Oscillator,
+    /// External clock signal fed into OSC_IN, the oscillator is bypassed
+    Bypass,
+}
+
+/// LSE oscillator drive capability (RCC_BDCR LSEDRV)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LseDrive {
+    /// Lowest drive, lowest consumption
+    Low = 0b00,
+    /// Medium low drive
+    MediumLow = 0b01,
+    /// Medium high drive
+    MediumHigh = 0b10,
+    /// Highest drive, for crystals that are hard to start
+    High = 0b11,
+}
+
+/// LSE configuration
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LseConfig {
+    /// Drive capability of the oscillator, ignored in bypass mode
+    pub drive: LseDrive,
+    /// External clock fed into OSC32_IN, the oscillator is bypassed
+    pub bypass: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct HseConfig {
+    speed: u32,
+    mode: HseMode,
+}
+
+/// Input clock of the main PLL
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PllSource {
+    /// Multi-speed internal clock
+    MSI = 0b01,
+    /// High-speed internal 16 MHz clock
+    HSI16 = 0b10,
+    /// High-speed external clock
+    HSE = 0b11,
+}
+
+/// System clock (SYSCLK) source
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SysClkSource {
+    /// Multi-speed internal clock
+    MSI = 0b00,
+    /// High-speed internal 16 MHz clock
+    HSI16 = 0b01,
+    /// High-speed external clock
+    HSE = 0b10,
+    /// Main PLL (PLLCLK)
+    PLL = 0b11,
+}
+
 /// Extension trait that constrains the `RCC` peripheral
 pub trait RccExt {
     /// Constrains the `RCC` peripheral so it plays nicely with the other abstractions
@@ -56,16 +136,8 @@ impl RccExt for RCC {
             bdcr: BDCR { _0: () },
             csr: CSR { _0: () },
             crrcr: CRRCR { _0: () },
-            cfgr: CFGR {
-                hclk: None,
-                hsi48: false,
-                msi: None,
-                lsi: false,
-                pclk1: None,
-                pclk2: None,
-                sysclk: None,
-                pllcfg: None,
-            },
+            cfgr: CFGR::default(),
+            cir: CIR { _0: () },
         }
     }
 }
@@ -92,6 +164,65 @@ pub struct Rcc {
     pub csr: CSR,
     /// Clock recovery RC register
     pub crrcr: CRRCR,
+    /// Clock interrupt registers
+    pub cir: CIR,
+}
+
+/// Applies a new clock configuration at runtime, e.g. to down-clock when idle
+///
+/// This is a plain alias of `CFGR::try_freeze`, named after its use case.
+/// Flash wait states are raised before and lowered after the clocks change,
+/// and SYSCLK temporarily runs from HSI16 while the PLL is reprogrammed.
+///
+/// `Rcc::cfgr` is usually moved out by the initial `freeze`, so the new
+/// configuration is built from scratch:
+///
+/// ```ignore
+/// let clocks = rcc.cfgr.sysclk(80.mhz()).freeze();
+/// // ...
+/// let idle = CFGR::default().msi(MsiFreq::RANGE2M).sysclk(2.mhz());
+/// let clocks = rcc::reconfigure(&idle)?;
+/// delay.set_clocks(clocks);
+/// ```
+///
+/// Peripherals already set up are not revisited. Drivers keep a copy of the
+/// `Clocks` they were created with, so they must be re-timed with the
+/// returned value, see `Serial::reconfigure`, `Timer::set_clocks`,
+/// `Delay::set_clocks` and `WindowWatchdog::set_clocks`; other drivers have
+/// to be re-created.
+pub fn reconfigure(cfgr: &CFGR) -> Result<Clocks, ClockError> {
+    cfgr.try_freeze()
+}
+
+/// Causes of the last reset, decoded from RCC_CSR
+///
+/// The flags accumulate until cleared, and a reset usually sets several of
+/// them: any reset also pulses NRST, so `pin` is set along with the others.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResetReason {
+    /// Firewall access violation (FWRSTF)
+    pub firewall: bool,
+    /// Option byte loading (OBLRSTF)
+    pub option_byte_load: bool,
+    /// NRST pin (PINRSTF)
+    pub pin: bool,
+    /// Brown-out, including power-on and exit from Standby/Shutdown (BORRSTF)
+    pub brown_out: bool,
+    /// Software reset through SCB AIRCR (SFTRSTF)
+    pub software: bool,
+    /// Independent watchdog (IWDGRSTF)
+    pub independent_watchdog: bool,
+    /// Window watchdog (WWDGRSTF)
+    pub window_watchdog: bool,
+    /// Illegal Stop, Standby or Shutdown entry (LPWRRSTF)
+    pub low_power: bool,
+}
+
+impl ResetReason {
+    /// Returns whether one of the watchdogs caused the reset
+    pub fn is_watchdog(&self) -> bool {
+        self.independent_watchdog || self.window_watchdog
+    }
 }
 
 /// CSR Control/Status Register
@@ -100,12 +231,33 @@ pub struct CSR {
 }
 
 impl CSR {
-    // TODO remove `allow`
-    #[allow(dead_code)]
     pub(crate) fn csr(&mut self) -> &rcc::CSR {
         // NOTE(unsafe) this proxy grants exclusive access to this register
         unsafe { &(*RCC::ptr()).csr }
     }
+
+    /// Returns the causes of the last reset
+    ///
+    /// The flags are kept until `clear_reset_flags`, which should be called
+    /// once they were read, so that the next reset is reported on its own.
+    pub fn reset_reason(&mut self) -> ResetReason {
+        let csr = self.csr().read();
+        ResetReason {
+            firewall: csr.fwrstf().bit_is_set(),
+            option_byte_load: csr.oblrstf().bit_is_set(),
+            pin: csr.pinrstf().bit_is_set(),
+            brown_out: csr.borrstf().bit_is_set(),
+            software: csr.sftrstf().bit_is_set(),
+            independent_watchdog: csr.iwdgrstf().bit_is_set(),
+            window_watchdog: csr.wwdgrstf().bit_is_set(),
+            low_power: csr.lpwrstf().bit_is_set(),
+        }
+    }
+
+    /// Clears the reset flags (RMVF)
+    pub fn clear_reset_flags(&mut self) {
+        self.csr().modify(|_, w| w.rmvf().set_bit());
+    }
 }
 
 /// Clock recovery RC register
@@ -141,6 +293,178 @@ impl BDCR {
         // NOTE(unsafe) this proxy grants exclusive access to this register
         unsafe { &(*RCC::ptr()).bdcr }
     }
+
+    /// Enables the clock security system (CSS) on LSE
+    ///
+    /// Both LSE and LSI must be running, and the reference manual requires the
+    /// RTC clock to be selected beforehand, i.e. call this after `Rtc::rtc`.
+    /// A failure is reported through `CIR::is_lse_failed`.
+    pub fn enable_lse_css(&mut self, clocks: &Clocks) -> Result<(), ClockError> {
+        if !clocks.lse() {
+            return Err(ClockError::SourceDisabled(Source::LSE));
+        }
+        if !clocks.lsi() {
+            return Err(ClockError::SourceDisabled(Source::LSI));
+        }
+        self.enr().modify(|_, w| w.lsecsson().set_bit());
+        Ok(())
+    }
+
+    /// Returns whether the LSE CSS detected a failure (LSECSSD)
+    ///
+    /// Unlike `CIR::is_lse_failed`, this flag is kept in the backup domain and
+    /// remains set until LSE CSS is disabled or the backup domain is reset.
+    pub fn is_lse_css_detected(&mut self) -> bool {
+        self.enr().read().lsecssd().bit_is_set()
+    }
+}
+
+/// Clock interrupt registers (CIER, CIFR, CICR)
+pub struct CIR {
+    _0: (),
+}
+
+impl CIR {
+    /// Creates a handle to the clock interrupt registers from thin air
+    ///
+    /// This is meant for the NMI handler, which preempts any critical section
+    /// and therefore cannot share the `CIR` owned by the application:
+    ///
+    /// ```ignore
+    /// #[exception]
+    /// fn NonMaskableInt() {
+    ///     // NOTE(unsafe) the application only accesses `CIR` from this handler
+    ///     let mut cir = unsafe { CIR::steal() };
+    ///     if cir.is_hse_failed() {
+    ///         // HSE is dead, the hardware already switched SYSCLK to HSI16
+    ///         let fallback = CFGR::default().sysclk(80.mhz());
+    ///         let clocks = cir.recover_from_hse_failure(&fallback).unwrap();
+    ///         // hand `clocks` over to the application, which re-times its drivers
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure no other `CIR` is used concurrently.
+    pub unsafe fn steal() -> Self {
+        CIR { _0: () }
+    }
+
+    pub(crate) fn cifr(&mut self) -> &rcc::CIFR {
+        // NOTE(unsafe) this proxy grants exclusive access to this register
+        unsafe { &(*RCC::ptr()).cifr }
+    }
+
+    pub(crate) fn cicr(&mut self) -> &rcc::CICR {
+        // NOTE(unsafe) this proxy grants exclusive access to this register
+        unsafe { &(*RCC::ptr()).cicr }
+    }
+
+    pub(crate) fn cier(&mut self) -> &rcc::CIER {
+        // NOTE(unsafe) this proxy grants exclusive access to this register
+        unsafe { &(*RCC::ptr()).cier }
+    }
+
+    /// Returns whether the CSS detected an HSE failure (CSSF)
+    pub fn is_hse_failed(&mut self) -> bool {
+        self.cifr().read().cssf().bit_is_set()
+    }
+
+    /// Returns whether the CSS detected an LSE failure (LSECSSF)
+    pub fn is_lse_failed(&mut self) -> bool {
+        self.cifr().read().lsecssf().bit_is_set()
+    }
+
+    /// Clears the HSE failure flag (CSSC), which otherwise keeps triggering the NMI
+    pub fn clear_hse_failed(&mut self) {
+        self.cicr().write(|w| w.cssc().set_bit());
+    }
+
+    /// Clears the LSE failure flag (LSECSSC)
+    pub fn clear_lse_failed(&mut self) {
+        self.cicr().write(|w| w.lsecssc().set_bit());
+    }
+
+    /// Starts raising the RCC interrupt on an LSE failure
+    ///
+    /// HSE failures always raise the non-maskable interrupt.
+    pub fn listen_lse_css(&mut self) {
+        self.cier().modify(|_, w| w.lsecssie().set_bit());
+    }
+
+    /// Stops raising the RCC interrupt on an LSE failure
+    pub fn unlisten_lse_css(&mut self) {
+        self.cier().modify(|_, w| w.lsecssie().clear_bit());
+    }
+
+    /// Recovers from an HSE failure by applying the `fallback` configuration
+    ///
+    /// Clears the CSS flag (which otherwise keeps triggering the NMI), turns
+    /// HSE off, and freezes `fallback`, which must not use HSE. The hardware has
+    /// already switched SYSCLK to HSI16 and stopped any PLL fed by HSE, so
+    /// `fallback` typically runs from HSI16 or MSI, possibly through the PLL.
+    pub fn recover_from_hse_failure(&mut self, fallback: &CFGR) -> Result<Clocks, ClockError> {
+        if fallback.hse.is_some() || fallback.pll_source == Some(PllSource::HSE) {
+            return Err(ClockError::SourceDisabled(Source::HSE));
+        }
+
+        self.clear_hse_failed();
+
+        let rcc = unsafe { &*RCC::ptr() };
+        // CSSON is set-only, the detector was already disabled by hardware
+        rcc.cr.modify(|_, w| w.hseon().clear_bit());
+
+        fallback.try_freeze()
+    }
+
+    /// Recovers from an LSE failure
+    ///
+    /// Clears the CSS flag, disables LSE CSS, MSI PLL-mode and LSE, and returns
+    /// `clocks` updated accordingly. Kernel clocks fed by LSE are reported as
+    /// 0 Hz; the RTC keeps running from LSI only after being re-created.
+    pub fn recover_from_lse_failure(&mut self, clocks: &Clocks) -> Clocks {
+        self.clear_lse_failed();
+
+        let rcc = unsafe { &*RCC::ptr() };
+        // MSI PLL-mode must be left before LSE is disabled
+        rcc.cr.modify(|_, w| w.msipllen().clear_bit());
+        rcc.bdcr.modify(|_, w| w.lsecsson().clear_bit().lseon().clear_bit());
+
+        let mut clocks = *clocks;
+        clocks.lse = false;
+        clocks.msi_pll = false;
+        let sel = clocks.ccipr;
+        let kernel = &mut clocks.kernel;
+        if sel.usart1 == UsartClkSource::LSE {
+            kernel.usart1 = 0;
+        }
+        if sel.usart2 == UsartClkSource::LSE {
+            kernel.usart2 = 0;
+        }
+        if sel.usart3 == UsartClkSource::LSE {
+            kernel.usart3 = 0;
+        }
+        #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+        {
+            if sel.uart4 == UsartClkSource::LSE {
+                kernel.uart4 = 0;
+            }
+            if sel.uart5 == UsartClkSource::LSE {
+                kernel.uart5 = 0;
+            }
+        }
+        if sel.lpuart1 == UsartClkSource::LSE {
+            kernel.lpuart1 = 0;
+        }
+        if sel.lptim1 == LptimClkSource::LSE {
+            kernel.lptim1 = 0;
+        }
+        if sel.lptim2 == LptimClkSource::LSE {
+            kernel.lptim2 = 0;
+        }
+        clocks
+    }
 }
 
 /// AMBA High-performance Bus 1 (AHB1) registers
@@ -255,20 +579,67 @@ impl APB2 {
 }
 
 const HSI: u32 = 16_000_000; // Hz
+const LSI: u32 = 32_000; // Hz
+const LSE: u32 = 32_768; // Hz
 
 /// Clock configuration
 pub struct CFGR {
     hclk: Option<u32>,
+    hse: Option<HseConfig>,
+    css: bool,
     hsi48: bool,
+    lse: Option<LseConfig>,
     msi: Option<MsiFreq>,
     lsi: bool,
+    msi_pll: bool,
     pclk1: Option<u32>,
     pclk2: Option<u32>,
     sysclk: Option<u32>,
-    pllcfg: Option<PllConfig>
+    pllcfg: Option<PllConfig>,
+    voltage_range: Option<VoltageRange>,
+    pll_source: Option<PllSource>,
+    pll_p: Option<PllPDiv>,
+    pll_q: Option<PllDivider>,
+    pllsai1: Option<PllSai1Config>,
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    pllsai2: Option<PllSai2Config>,
+    kernel: KernelClockSelection,
+}
+
+impl Default for CFGR {
+    fn default() -> Self {
+        CFGR {
+            hclk: None,
+            hse: None,
+            css: false,
+            hsi48: false,
+            lse: None,
+            msi: None,
+            lsi: false,
+            msi_pll: false,
+            pclk1: None,
+            pclk2: None,
+            sysclk: None,
+            pllcfg: None,
+            voltage_range: None,
+            pll_source: None,
+            pll_p: None,
+            pll_q: None,
+            pllsai1: None,
+            #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+            pllsai2: None,
+            kernel: KernelClockSelection::default(),
+        }
+    }
 }
 
 impl CFGR {
+    pub(crate) fn cfgr(&mut self) -> &rcc::CFGR {
+        // NOTE(unsafe) outside of freezing, only the MCO fields are written,
+        // through this proxy
+        unsafe { &(*RCC::ptr()).cfgr }
+    }
+
     /// Sets a frequency for the AHB bus
     pub fn hclk<F>(mut self, freq: F) -> Self
     where
@@ -278,6 +649,30 @@ impl CFGR {
         self
     }
 
+    /// Enables the high-speed external oscillator (HSE) running at `freq`
+    ///
+    /// When enabled, HSE is used as the PLL input instead of HSI16, and drives
+    /// the system clock directly if the requested `sysclk` equals `freq`.
+    pub fn hse<F>(mut self, freq: F, mode: HseMode) -> Self
+    where
+        F: Into<Hertz>,
+    {
+        self.hse = Some(HseConfig {
+            speed: freq.into().0,
+            mode,
+        });
+        self
+    }
+
+    /// Enables the clock security system (CSS) on HSE
+    ///
+    /// If HSE fails, the hardware switches the system clock to HSI16 and raises
+    /// a non-maskable interrupt, see `CIR::recover_from_hse_failure`.
+    pub fn hse_css(mut self, on: bool) -> Self {
+        self.css = on;
+        self
+    }
+
     /// Enable the 48Mh USB, RNG, SDMMC clock source. Not available on all stm32l4x6 series
     pub fn hsi48(mut self, on: bool) -> Self
     {
@@ -285,6 +680,10 @@ impl CFGR {
         self
     }
 
+    /// Enables MSI at `range`
+    ///
+    /// MSI drives SYSCLK directly when `sysclk` is set to the frequency of
+    /// `range`, e.g. `.msi(MsiFreq::RANGE2M).sysclk(2.mhz())` for low-power run.
     pub fn msi(mut self, range: MsiFreq) -> Self
     {
         self.msi = Some(range);
@@ -298,6 +697,26 @@ impl CFGR {
         self
     }
 
+    /// Enables the 32.768 kHz low-speed external oscillator (LSE)
+    ///
+    /// LSE lives in the backup domain: `freeze` enables write access to it, and
+    /// leaves an already running LSE untouched, as it survives system resets.
+    pub fn lse(mut self, cfg: LseConfig) -> Self
+    {
+        self.lse = Some(cfg);
+        self
+    }
+
+    /// Enables the MSI PLL-mode, which continuously trims MSI against LSE
+    ///
+    /// Requires LSE. With MSI at 48 MHz, this is accurate enough to clock USB
+    /// without a crystal.
+    pub fn msi_pll_mode(mut self, on: bool) -> Self
+    {
+        self.msi_pll = on;
+        self
+    }
+
     /// Sets a frequency for the APB1 bus
     pub fn pclk1<F>(mut self, freq: F) -> Self
     where
@@ -326,6 +745,9 @@ impl CFGR {
     }
 
     /// Sets the system (core) frequency with some pll configuration
+    ///
+    /// `cfg` must keep the PLL within the limits of the voltage range, otherwise
+    /// freezing fails with `ClockError::PllUnreachable`.
     pub fn sysclk_with_pll<F>(mut self, freq: F, cfg: PllConfig) -> Self
     where
         F: Into<Hertz>,
@@ -335,171 +757,418 @@ impl CFGR {
         self
     }
 
+    /// Sets the voltage range to run in, by default the active range is kept
+    ///
+    /// The frequencies are validated against the limits of `range`. The range
+    /// is raised before the clocks speed up, and lowered once they have slowed
+    /// down.
+    pub fn voltage_range(mut self, range: VoltageRange) -> Self {
+        self.voltage_range = Some(range);
+        self
+    }
+
+    /// Selects the input of the main PLL
+    ///
+    /// Defaults to HSE if enabled, HSI16 otherwise. When MSI is selected, it
+    /// runs at the range set via `msi`, and is programmed to 4 MHz if none was
+    /// given.
+    pub fn pll_source(mut self, source: PllSource) -> Self {
+        self.pll_source = Some(source);
+        self
+    }
+
+    /// Enables the P output of the main PLL (PLLSAI3CLK)
+    ///
+    /// The main PLL must be running, i.e. drive the system clock.
+    pub fn pll_p(mut self, div: PllPDiv) -> Self {
+        self.pll_p = Some(div);
+        self
+    }
+
+    /// Enables the Q output of the main PLL (PLL48M1CLK, for USB, RNG and SDMMC)
+    ///
+    /// The main PLL must be running, i.e. drive the system clock.
+    pub fn pll_q(mut self, div: PllDivider) -> Self {
+        self.pll_q = Some(div);
+        self
+    }
+
+    /// Enables PLLSAI1, which shares its input and M divider with the main PLL
+    pub fn pllsai1(mut self, cfg: PllSai1Config) -> Self {
+        self.pllsai1 = Some(cfg);
+        self
+    }
+
+    /// Enables PLLSAI2, which shares its input and M divider with the main PLL
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    pub fn pllsai2(mut self, cfg: PllSai2Config) -> Self {
+        self.pllsai2 = Some(cfg);
+        self
+    }
+
+    /// Selects the kernel clock of USART1
+    pub fn usart1_src(mut self, src: UsartClkSource) -> Self {
+        self.kernel.usart1 = Some(src);
+        self
+    }
+
+    /// Selects the kernel clock of USART2
+    pub fn usart2_src(mut self, src: UsartClkSource) -> Self {
+        self.kernel.usart2 = Some(src);
+        self
+    }
+
+    /// Selects the kernel clock of USART3
+    pub fn usart3_src(mut self, src: UsartClkSource) -> Self {
+        self.kernel.usart3 = Some(src);
+        self
+    }
+
+    /// Selects the kernel clock of UART4
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    pub fn uart4_src(mut self, src: UsartClkSource) -> Self {
+        self.kernel.uart4 = Some(src);
+        self
+    }
+
+    /// Selects the kernel clock of UART5
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    pub fn uart5_src(mut self, src: UsartClkSource) -> Self {
+        self.kernel.uart5 = Some(src);
+        self
+    }
+
+    /// Selects the kernel clock of LPUART1
+    pub fn lpuart1_src(mut self, src: UsartClkSource) -> Self {
+        self.kernel.lpuart1 = Some(src);
+        self
+    }
+
+    /// Selects the kernel clock of I2C1
+    pub fn i2c1_src(mut self, src: I2cClkSource) -> Self {
+        self.kernel.i2c1 = Some(src);
+        self
+    }
+
+    /// Selects the kernel clock of I2C2
+    pub fn i2c2_src(mut self, src: I2cClkSource) -> Self {
+        self.kernel.i2c2 = Some(src);
+        self
+    }
+
+    /// Selects the kernel clock of I2C3
+    pub fn i2c3_src(mut self, src: I2cClkSource) -> Self {
+        self.kernel.i2c3 = Some(src);
+        self
+    }
+
+    /// Selects the kernel clock of LPTIM1
+    pub fn lptim1_src(mut self, src: LptimClkSource) -> Self {
+        self.kernel.lptim1 = Some(src);
+        self
+    }
+
+    /// Selects the kernel clock of LPTIM2
+    pub fn lptim2_src(mut self, src: LptimClkSource) -> Self {
+        self.kernel.lptim2 = Some(src);
+        self
+    }
+
+    /// Selects the kernel clock of the ADCs
+    pub fn adc_src(mut self, src: AdcClkSource) -> Self {
+        self.kernel.adc = Some(src);
+        self
+    }
+
+    /// Selects the 48 MHz clock of USB, RNG and SDMMC
+    ///
+    /// Defaults to MSI if it runs at 48 MHz, HSI48 otherwise.
+    pub fn clk48_src(mut self, src: Clk48Source) -> Self {
+        self.kernel.clk48 = Some(src);
+        self
+    }
+
+    /// Selects the kernel clock of SAI1
+    pub fn sai1_src(mut self, src: SaiClkSource) -> Self {
+        self.kernel.sai1 = Some(src);
+        self
+    }
+
+    /// Selects the kernel clock of SAI2
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    pub fn sai2_src(mut self, src: SaiClkSource) -> Self {
+        self.kernel.sai2 = Some(src);
+        self
+    }
+
     /// Freezes the clock configuration, making it effective
+    ///
+    /// Panics if the configuration is invalid or an oscillator fails to start,
+    /// see `try_freeze` for the fallible version.
     // pub fn freeze(&self, acr: &mut ACR) -> Clocks {
     pub fn freeze(&self) -> Clocks {
+        self.try_freeze().unwrap()
+    }
 
-        let pllconf = if self.pllcfg.is_none() {
-            let plln = (2 * self.sysclk.unwrap_or(HSI)) / HSI;
-            let plln = cmp::min(cmp::max(plln, 2), 16);
-            if plln == 2 {
-                None
-            } else {
-                // create a best effort pll config, just multiply n
-                // TODO should we reject this configuration as the clocks stored in RCC could cause timing issues?
-                let conf = PllConfig {
-                    m: 0b0,
-                    r: 0b0,
-                    n: plln as u8
-                };
-                Some(conf)
-            }
+    /// Freezes the clock configuration, making it effective
+    ///
+    /// The configuration is validated before any register is touched. Waiting
+    /// for an oscillator, the PLL or the system clock switch is bounded, and
+    /// reported as `ClockError::OscillatorTimeout` instead of hanging; so are
+    /// the flash wait states and the voltage scaling.
+    pub fn try_freeze(&self) -> Result<Clocks, ClockError> {
+        let flash = unsafe { &*FLASH::ptr() };
+        let rcc = unsafe { &*RCC::ptr() };
 
-        } else {
-            let conf = self.pllcfg.unwrap();
-            Some(conf)
-        };
+        // the active voltage range is read from PWR, which needs its clock
+        rcc.apb1enr1.modify(|_, w| w.pwren().set_bit());
+        let old_range = pwr::voltage_range();
+        let range = self.voltage_range.unwrap_or(old_range);
+        let plan = self.plan(range)?;
 
-        let sysclk = self.sysclk.unwrap_or(HSI);
-
-        assert!(sysclk <= 80_000_000);
-
-        let (hpre_bits, hpre_div) = self.hclk
-            .map(|hclk| match sysclk / hclk {
-                // From p 194 in RM0394
-                0 => unreachable!(),
-                1 => (0b0000, 1),
-                2 => (0b1000, 2),
-                3...5 => (0b1001, 4),
-                6...11 => (0b1010, 8),
-                12...39 => (0b1011, 16),
-                40...95 => (0b1100, 64),
-                96...191 => (0b1101, 128),
-                192...383 => (0b1110, 256),
-                _ => (0b1111, 512),
-            })
-            .unwrap_or((0b0000, 1));
+        if range == VoltageRange::Range1 && old_range == VoltageRange::Range2 {
+            pwr::set_voltage_range(range)?;
+        }
 
-        let hclk = sysclk / hpre_div;
+        // Flash wait states must be added before the clocks speed up, and may
+        // only be removed once they have slowed down
+        let old_latency = flash.acr.read().latency().bits();
+        if plan.latency > old_latency {
+            set_flash_latency(plan.latency)?;
+        }
 
-        assert!(hclk <= sysclk);
-
-        let (ppre1_bits, ppre1) = self.pclk1
-            .map(|pclk1| match hclk / pclk1 {
-                // From p 194 in RM0394
-                0 => unreachable!(),
-                1 => (0b000, 1),
-                2 => (0b100, 2),
-                3...5 => (0b101, 4),
-                6...11 => (0b110, 8),
-                _ => (0b111, 16)
-            })
-            .unwrap_or((0b000, 1));
+        // Turn on the external oscillator, HSEBYP must be set before HSEON
+        if let Some(hse) = self.hse {
+            rcc.cr.modify(|_, w| w.hsebyp().bit(hse.mode == HseMode::Bypass));
+            rcc.cr.modify(|_, w| w.hseon().set_bit());
+            // Wait until HSE is running
+            wait_for(
+                || rcc.cr.read().hserdy().bit_is_set(),
+                ClockError::OscillatorTimeout(Source::HSE),
+                HSE_TIMEOUT,
+            )?;
+
+            if self.css {
+                rcc.cr.modify(|_, w| w.csson().set_bit());
+            }
+        }
 
-        let pclk1 = hclk / u32(ppre1);
+        if let Some(lse) = self.lse {
+            // LSE lives in the backup domain, which is write protected after reset
+            let pwr = unsafe { &*PWR::ptr() };
+            rcc.apb1enr1.modify(|_, w| w.pwren().set_bit());
+            pwr.cr1.modify(|_, w| w.dbp().set_bit());
+
+            // LSE keeps running across system resets, restarting it would take seconds
+            if rcc.bdcr.read().lserdy().bit_is_clear() {
+                rcc.bdcr.modify(|_, w| unsafe {
+                    w.lseon().clear_bit()
+                        .lsebyp().bit(lse.bypass)
+                        .lsedrv().bits(lse.drive as u8)
+                });
+                rcc.bdcr.modify(|_, w| w.lseon().set_bit());
+            }
+            // Wait until LSE is running
+            wait_for(
+                || rcc.bdcr.read().lserdy().bit_is_set(),
+                ClockError::OscillatorTimeout(Source::LSE),
+                LSE_TIMEOUT,
+            )?;
+        }
 
-        assert!(pclk1 <= sysclk);
-
-        let (ppre2_bits, ppre2) = self.pclk2
-            .map(|pclk2| match hclk / pclk2 {
-                // From p 194 in RM0394
-                0 => unreachable!(),
-                1 => (0b000, 1),
-                2 => (0b100, 2),
-                3...5 => (0b101, 4),
-                6...11 => (0b110, 8),
-                _ => (0b111, 16)
-            })
-            .unwrap_or((0b000, 1));
+        if (plan.pll_m.is_some() || plan.msi.is_some())
+            && rcc.cfgr.read().sws().bits() == SysClkSource::PLL as u8
+        {
+            // The PLL cannot be stopped, nor MSI feeding it retuned, while it
+            // drives SYSCLK: run from HSI16 meanwhile
+            let hsi_latency = flash_latency(pwr::voltage_range(), HSI);
+            if flash.acr.read().latency().bits() < hsi_latency {
+                set_flash_latency(hsi_latency)?;
+            }
+            rcc.cr.modify(|_, w| w.hsion().set_bit());
+            wait_for(
+                || rcc.cr.read().hsirdy().bit_is_set(),
+                ClockError::OscillatorTimeout(Source::HSI16),
+                READY_TIMEOUT,
+            )?;
+            rcc.cfgr.modify(|_, w| unsafe { w.sw().bits(SysClkSource::HSI16 as u8) });
+            wait_for(
+                || rcc.cfgr.read().sws().bits() == SysClkSource::HSI16 as u8,
+                ClockError::OscillatorTimeout(Source::HSI16),
+                READY_TIMEOUT,
+            )?;
+        }
 
-        let pclk2 = hclk / u32(ppre2);
+        if let Some(msi) = plan.msi {
+            // MSI may already drive SYSCLK, as it does after reset, in which
+            // case the wait states must cover its new frequency first
+            if rcc.cfgr.read().sws().bits() == SysClkSource::MSI as u8 {
+                let msi_latency = flash_latency(pwr::voltage_range(), msi.to_hertz().0);
+                if flash.acr.read().latency().bits() < msi_latency {
+                    set_flash_latency(msi_latency)?;
+                }
+            }
+            unsafe { rcc.cr.modify(|_, w| w.msirange().bits(msi as u8).msirgsel().set_bit().msion().set_bit() )};
+            // Wait until MSI is running
+            wait_for(
+                || rcc.cr.read().msirdy().bit_is_set(),
+                ClockError::OscillatorTimeout(Source::MSI),
+                READY_TIMEOUT,
+            )?;
+        }
 
-        assert!(pclk2 <= sysclk);
-
-        // adjust flash wait states
-        unsafe {
-            (&*FLASH::ptr()).acr.write(|w| {
-                w.latency().bits(if sysclk <= 24_000_000 {
-                    0b000
-                } else if sysclk <= 48_000_000 {
-                    0b001
-                } else {
-                    0b010
-                })
-            })
+        // MSI PLL-mode, LSE is known to be ready at this point
+        rcc.cr.modify(|_, w| w.msipllen().bit(self.msi_pll));
+
+        if plan.sysclk_src == SysClkSource::HSI16
+            || (plan.pll_m.is_some() && plan.pll_source == PllSource::HSI16)
+            || plan.hsi16_kernel
+        {
+            rcc.cr.modify(|_, w| w.hsion().set_bit());
+            wait_for(
+                || rcc.cr.read().hsirdy().bit_is_set(),
+                ClockError::OscillatorTimeout(Source::HSI16),
+                READY_TIMEOUT,
+            )?;
         }
 
-        let rcc = unsafe { &*RCC::ptr() };
-        let sysclk_src_bits;
-        if let Some(pllconf) = pllconf {
-            // use PLL as source
-            sysclk_src_bits = 0b11;
+        if let Some(pll_m) = plan.pll_m {
+            // PLLSRC and PLLM are shared by all PLLs, and can only be written
+            // while all of them are disabled
+            rcc.cr.modify(|_, w| w.pllsai1on().clear_bit());
+            wait_for(
+                || rcc.cr.read().pllsai1rdy().bit_is_clear(),
+                ClockError::OscillatorTimeout(Source::PLLSAI1),
+                READY_TIMEOUT,
+            )?;
+            #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+            {
+                rcc.cr.modify(|_, w| w.pllsai2on().clear_bit());
+                wait_for(
+                    || rcc.cr.read().pllsai2rdy().bit_is_clear(),
+                    ClockError::OscillatorTimeout(Source::PLLSAI2),
+                    READY_TIMEOUT,
+                )?;
+            }
             rcc.cr.modify(|_, w| w.pllon().clear_bit());
-            while rcc.cr.read().pllrdy().bit_is_set() {}
-
-            let pllsrc_bits = 0b10; // use HSI16 as PLL source
-            rcc.cr.write(|w| w.hsion().set_bit());
-            while rcc.cr.read().hsirdy().bit_is_clear() {}
+            wait_for(
+                || rcc.cr.read().pllrdy().bit_is_clear(),
+                ClockError::OscillatorTimeout(Source::PLL),
+                READY_TIMEOUT,
+            )?;
 
             rcc.pllcfgr
             .modify(|_, w| unsafe {
                 w.pllsrc()
-                    .bits(pllsrc_bits)
-                    .pllm().bits(pllconf.m)
-                    .pllr().bits(pllconf.r)
+                    .bits(plan.pll_source as u8)
+                    .pllm().bits(pll_m)
+            });
+        }
+
+        if let Some(pllconf) = plan.pllconf {
+            // use PLL as source
+            rcc.pllcfgr
+            .modify(|_, w| unsafe {
+                w.pllr().bits(pllconf.r)
                     .plln().bits(pllconf.n)
+                    .pllp().bit(self.pll_p == Some(PllPDiv::Div17))
+                    .pllpen().bit(self.pll_p.is_some())
+                    .pllq().bits(self.pll_q.unwrap_or(PllDivider::Div2) as u8)
+                    .pllqen().bit(self.pll_q.is_some())
             });
 
             rcc.cr.modify(|_, w| w.pllon().set_bit());
 
-            while rcc.cr.read().pllrdy().bit_is_clear() {}
+            wait_for(
+                || rcc.cr.read().pllrdy().bit_is_set(),
+                ClockError::OscillatorTimeout(Source::PLL),
+                READY_TIMEOUT,
+            )?;
 
             rcc.pllcfgr.modify(|_, w| w.pllren().set_bit());
+        }
 
-            // SW: PLL selected as system clock
-            rcc.cfgr.modify(|_, w| unsafe {
-                w.ppre2()
-                    .bits(ppre2_bits)
-                    .ppre1()
-                    .bits(ppre1_bits)
-                    .hpre()
-                    .bits(hpre_bits)
-                    .sw()
-                    .bits(sysclk_src_bits)
-            });
-        } else {
-            // use HSI as source
-            sysclk_src_bits = 0b01;
-
-            rcc.cr.write(|w| w.hsion().set_bit());
-            while rcc.cr.read().hsirdy().bit_is_clear() {}
-
-            // SW: HSI selected as system clock
-            rcc.cfgr.write(|w| unsafe {
-                w.ppre2()
-                    .bits(ppre2_bits)
-                    .ppre1()
-                    .bits(ppre1_bits)
-                    .hpre()
-                    .bits(hpre_bits)
-                    .sw()
-                    .bits(sysclk_src_bits)
+        if let Some(sai1) = self.pllsai1 {
+            rcc.pllsai1cfgr.write(|w| unsafe {
+                w.pllsai1n().bits(sai1.n)
+                    .pllsai1p().bit(sai1.p == Some(PllPDiv::Div17))
+                    .pllsai1pen().bit(sai1.p.is_some())
+                    .pllsai1q().bits(sai1.q.unwrap_or(PllDivider::Div2) as u8)
+                    .pllsai1qen().bit(sai1.q.is_some())
+                    .pllsai1r().bits(sai1.r.unwrap_or(PllDivider::Div2) as u8)
+                    .pllsai1ren().bit(sai1.r.is_some())
             });
+            rcc.cr.modify(|_, w| w.pllsai1on().set_bit());
+            wait_for(
+                || rcc.cr.read().pllsai1rdy().bit_is_set(),
+                ClockError::OscillatorTimeout(Source::PLLSAI1),
+                READY_TIMEOUT,
+            )?;
         }
 
-        while rcc.cfgr.read().sws().bits() != sysclk_src_bits {}
+        #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+        {
+            if let Some(sai2) = self.pllsai2 {
+                rcc.pllsai2cfgr.write(|w| unsafe {
+                    w.pllsai2n().bits(sai2.n)
+                        .pllsai2p().bit(sai2.p == Some(PllPDiv::Div17))
+                        .pllsai2pen().bit(sai2.p.is_some())
+                        .pllsai2r().bits(sai2.r.unwrap_or(PllDivider::Div2) as u8)
+                        .pllsai2ren().bit(sai2.r.is_some())
+                });
+                rcc.cr.modify(|_, w| w.pllsai2on().set_bit());
+                wait_for(
+                    || rcc.cr.read().pllsai2rdy().bit_is_set(),
+                    ClockError::OscillatorTimeout(Source::PLLSAI2),
+                    READY_TIMEOUT,
+                )?;
+            }
+        }
+
+        // SW: PLL, HSI or HSE selected as system clock
+        rcc.cfgr.modify(|_, w| unsafe {
+            w.ppre2()
+                .bits(plan.ppre2_bits)
+                .ppre1()
+                .bits(plan.ppre1_bits)
+                .hpre()
+                .bits(plan.hpre_bits)
+                .sw()
+                .bits(plan.sysclk_src as u8)
+        });
+
+        wait_for(
+            || rcc.cfgr.read().sws().bits() == plan.sysclk_src as u8,
+            plan.sysclk_src.into(),
+            READY_TIMEOUT,
+        )?;
+
+        if plan.pll_m.is_none() {
+            // None of the PLLs is used anymore, e.g. after down-clocking
+            rcc.cr.modify(|_, w| w.pllon().clear_bit().pllsai1on().clear_bit());
+            #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+            rcc.cr.modify(|_, w| w.pllsai2on().clear_bit());
+        }
+
+        if flash.acr.read().latency().bits() != plan.latency {
+            set_flash_latency(plan.latency)?;
+        }
+
+        // Range 2 needs at least as many wait states as Range 1, so the
+        // latency above is already correct for it
+        if range == VoltageRange::Range2 && old_range == VoltageRange::Range1 {
+            pwr::set_voltage_range(range)?;
+        }
 
         // Turn on the internal 32khz lsi oscillator
         if self.lsi {
             rcc.csr.modify(|_, w| w.lsion().set_bit());
             // Wait until LSI is running
-            while rcc.csr.read().lsirdy().bit_is_clear() {}
-        }
-
-        if let Some(msi) = self.msi {
-            unsafe { rcc.cr.modify(|_, w| w.msirange().bits(msi as u8).msirgsel().set_bit().msion().set_bit() )};
-            // Wait until MSI is running
-            while rcc.cr.read().msirdy().bit_is_clear() {}
+            wait_for(
+                || rcc.csr.read().lsirdy().bit_is_set(),
+                ClockError::OscillatorTimeout(Source::LSI),
+                READY_TIMEOUT,
+            )?;
         }
 
         {
@@ -508,41 +1177,959 @@ impl CFGR {
                 // p. 180 in ref-manual
                 rcc.crrcr.modify(|_, w| w.hsi48on().set_bit());
                 // Wait until HSI48 is running
-                while rcc.crrcr.read().hsi48rdy().bit_is_clear() {}
+                wait_for(
+                    || rcc.crrcr.read().hsi48rdy().bit_is_set(),
+                    ClockError::OscillatorTimeout(Source::HSI48),
+                    READY_TIMEOUT,
+                )?;
             }
         }
 
-        // Select MSI as clock source for usb48, rng ...
-        if let Some(MsiFreq::RANGE48M) = self.msi {
-            unsafe { rcc.ccipr.modify(|_, w| w.clk48sel().bits(MsiFreq::RANGE48M as u8)) };
-        }
-        //TODO proper clk48sel and other selects
-
-        Clocks {
-            hclk: Hertz(hclk),
+        // Select the kernel clocks of the peripherals
+        let sel = plan.ccipr;
+        rcc.ccipr.modify(|_, w| unsafe {
+            w.usart1sel().bits(sel.usart1 as u8)
+                .usart2sel().bits(sel.usart2 as u8)
+                .usart3sel().bits(sel.usart3 as u8)
+                .lpuart1sel().bits(sel.lpuart1 as u8)
+                .i2c1sel().bits(sel.i2c1 as u8)
+                .i2c2sel().bits(sel.i2c2 as u8)
+                .i2c3sel().bits(sel.i2c3 as u8)
+                .lptim1sel().bits(sel.lptim1 as u8)
+                .lptim2sel().bits(sel.lptim2 as u8)
+                .adcsel().bits(sel.adc as u8)
+                .clk48sel().bits(sel.clk48 as u8)
+                .sai1sel().bits(sel.sai1 as u8)
+        });
+        #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+        rcc.ccipr.modify(|_, w| unsafe {
+            w.uart4sel().bits(sel.uart4 as u8)
+                .uart5sel().bits(sel.uart5 as u8)
+                .sai2sel().bits(sel.sai2 as u8)
+        });
+
+        Ok(Clocks {
+            hclk: Hertz(plan.hclk),
+            hse: self.hse.map(|hse| Hertz(hse.speed)),
+            lse: self.lse.is_some(),
             lsi: self.lsi,
-            msi: self.msi,
+            msi_pll: self.msi_pll,
+            msi: plan.msi,
             hsi48: self.hsi48,
-            pclk1: Hertz(pclk1),
-            pclk2: Hertz(pclk2),
-            ppre1: ppre1,
-            ppre2: ppre2,
-            sysclk: Hertz(sysclk),
-        }
+            pclk1: Hertz(plan.pclk1),
+            pclk2: Hertz(plan.pclk2),
+            ppre1: plan.ppre1,
+            ppre2: plan.ppre2,
+            sysclk: Hertz(plan.sysclk),
+            sysclk_src: plan.sysclk_src,
+            pll_source: plan.pll_m.map(|_| plan.pll_source),
+            pllsai3clk: plan.pllsai3clk.map(Hertz),
+            pll48m1clk: plan.pll48m1clk.map(Hertz),
+            pllsai1clk: plan.pllsai1clk.map(Hertz),
+            pll48m2clk: plan.pll48m2clk.map(Hertz),
+            plladc1clk: plan.plladc1clk.map(Hertz),
+            #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+            pllsai2clk: plan.pllsai2clk.map(Hertz),
+            #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+            plladc2clk: plan.plladc2clk.map(Hertz),
+            kernel: plan.kernel,
+            ccipr: plan.ccipr,
+            voltage_range: range,
+        })
     }
 
-}
+    /// Validates the configuration and computes the resulting clock tree
+    ///
+    /// This does not access any register.
+    fn plan(&self, range: VoltageRange) -> Result<ClockPlan, ClockError> {
+        let limits = RangeLimits::of(range);
+        if let Some(hse) = self.hse {
+            if hse.speed > limits.hse {
+                return Err(ClockError::VoltageRangeExceeded(Source::HSE));
+            }
+        }
+        if let Some(msi) = self.msi {
+            if msi.to_hertz().0 > limits.msi {
+                return Err(ClockError::VoltageRangeExceeded(Source::MSI));
+            }
+        }
 
-#[derive(Clone, Copy)]
-/// Pll Configuration - Calculation = ((SourceClk / m) * n) / r
-pub struct PllConfig {
-    /// Main PLL Division factor
-    pub m: u8,
-    /// Main Pll Multiplication factor
-    pub n: u8,
-    /// Main PLL division factor for PLLCLK (system clock)
-    pub r: u8,
-}
+        let pll_source = self.pll_source.unwrap_or(if self.hse.is_some() {
+            PllSource::HSE
+        } else {
+            PllSource::HSI16
+        });
+        let pll_input = match pll_source {
+            PllSource::MSI => self.msi.unwrap_or(MsiFreq::RANGE4M).to_hertz().0,
+            PllSource::HSI16 => HSI,
+            PllSource::HSE => match self.hse {
+                Some(hse) => hse.speed,
+                None => return Err(ClockError::SourceDisabled(Source::HSE)),
+            },
+        };
+
+        // without PLL, SYSCLK is driven by HSE if enabled, by MSI if the
+        // requested frequency is that of its range, and by HSI16 otherwise
+        let msi_direct = self.msi.map(|msi| msi.to_hertz().0).filter(|&msi| self.sysclk == Some(msi));
+        let (direct_src, direct) = match (self.hse, msi_direct) {
+            (Some(hse), _) if self.sysclk.map_or(true, |sysclk| sysclk == hse.speed) => {
+                (SysClkSource::HSE, hse.speed)
+            }
+            (_, Some(msi)) => (SysClkSource::MSI, msi),
+            (Some(hse), None) => (SysClkSource::HSE, hse.speed),
+            (None, None) => (SysClkSource::HSI16, HSI),
+        };
+        let requested = self.sysclk.unwrap_or(direct);
+
+        if self.css && self.hse.is_none() {
+            return Err(ClockError::SourceDisabled(Source::HSE));
+        }
+
+        if requested > limits.sysclk {
+            return Err(ClockError::SysclkTooHigh);
+        }
+
+        let pllconf = match self.pllcfg {
+            Some(conf) => {
+                check_pll(pll_input, &conf, &limits)?;
+                Some(conf)
+            }
+            None if requested == direct => None,
+            None => Some(solve_pll_within(pll_input, requested, &limits)?),
+        };
+
+        // the frequency actually produced, which may differ slightly from the request
+        let (sysclk_src, sysclk) = match pllconf {
+            Some(conf) => (SysClkSource::PLL, conf.pllclk(pll_input)),
+            None => (direct_src, requested),
+        };
+
+        if sysclk > limits.sysclk {
+            return Err(ClockError::SysclkTooHigh);
+        }
+
+        #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+        let pllsai2_used = self.pllsai2.is_some();
+        #[cfg(not(any(feature = "stm32l4x5", feature = "stm32l4x6")))]
+        let pllsai2_used = false;
+
+        // PLLSAI1 and PLLSAI2 share the input and M divider of the main PLL
+        let pll_m = match pllconf {
+            Some(conf) => Some(conf.m),
+            None if self.pllsai1.is_some() || pllsai2_used => Some(pll_m_for(pll_input)?),
+            None => None,
+        };
+
+        // MSI feeding the PLLs is programmed to 4 MHz unless a range was given
+        let msi_range = match self.msi {
+            None if pll_m.is_some() && pll_source == PllSource::MSI => Some(MsiFreq::RANGE4M),
+            msi => msi,
+        };
+
+        // P and Q outputs of the main PLL
+        let (pllsai3clk, pll48m1clk) = match pllconf {
+            Some(conf) => {
+                let vco = conf.vco(pll_input);
+                let out = |divisor| pll_output(vco, divisor, &limits, Source::PLL);
+                (
+                    self.pll_p.map(|p| out(p.divisor())).transpose()?,
+                    self.pll_q.map(|q| out(q.divisor())).transpose()?,
+                )
+            }
+            None if self.pll_p.is_some() || self.pll_q.is_some() => {
+                return Err(ClockError::SourceDisabled(Source::PLL))
+            }
+            None => (None, None),
+        };
+
+        let (pllsai1clk, pll48m2clk, plladc1clk) = match (self.pllsai1, pll_m) {
+            (Some(sai1), Some(m)) => {
+                let vco = sai_vco(pll_input, m, sai1.n, limits.vco)?;
+                let out = |divisor| pll_output(vco, divisor, &limits, Source::PLLSAI1);
+                (
+                    sai1.p.map(|p| out(p.divisor())).transpose()?,
+                    sai1.q.map(|q| out(q.divisor())).transpose()?,
+                    sai1.r.map(|r| out(r.divisor())).transpose()?,
+                )
+            }
+            _ => (None, None, None),
+        };
+
+        #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+        let (pllsai2clk, plladc2clk) = match (self.pllsai2, pll_m) {
+            (Some(sai2), Some(m)) => {
+                let vco = sai_vco(pll_input, m, sai2.n, limits.vco)?;
+                let out = |divisor| pll_output(vco, divisor, &limits, Source::PLLSAI2);
+                (
+                    sai2.p.map(|p| out(p.divisor())).transpose()?,
+                    sai2.r.map(|r| out(r.divisor())).transpose()?,
+                )
+            }
+            _ => (None, None),
+        };
+
+        let (hpre_bits, hpre_div) = match self.hclk {
+            Some(hclk) => hpre(sysclk, hclk)?,
+            None => (0b0000, 1),
+        };
+        let hclk = sysclk / hpre_div;
+
+        let (ppre1_bits, ppre1) = match self.pclk1 {
+            Some(pclk1) => ppre(hclk, pclk1)?,
+            None => (0b000, 1),
+        };
+        let pclk1 = hclk / u32(ppre1);
+
+        let (ppre2_bits, ppre2) = match self.pclk2 {
+            Some(pclk2) => ppre(hclk, pclk2)?,
+            None => (0b000, 1),
+        };
+        let pclk2 = hclk / u32(ppre2);
+
+        let lse = self.lse.map(|_| LSE);
+        if self.msi_pll && lse.is_none() {
+            return Err(ClockError::SourceDisabled(Source::LSE));
+        }
+        let lsi = if self.lsi { Some(LSI) } else { None };
+        let msi = msi_range.map(|msi| msi.to_hertz().0);
+        let hsi48 = if self.hsi48 { Some(48_000_000) } else { None };
+
+        let sel = self.kernel;
+        let usart = |src: Option<UsartClkSource>, pclk: u32| -> Result<(UsartClkSource, u32), ClockError> {
+            let src = src.unwrap_or(UsartClkSource::PCLK);
+            Ok((src, match src {
+                UsartClkSource::PCLK => pclk,
+                UsartClkSource::SYSCLK => sysclk,
+                UsartClkSource::HSI16 => HSI,
+                UsartClkSource::LSE => lse.ok_or(ClockError::SourceDisabled(Source::LSE))?,
+            }))
+        };
+        let i2c = |src: Option<I2cClkSource>| -> (I2cClkSource, u32) {
+            let src = src.unwrap_or(I2cClkSource::PCLK);
+            (src, match src {
+                I2cClkSource::PCLK => pclk1,
+                I2cClkSource::SYSCLK => sysclk,
+                I2cClkSource::HSI16 => HSI,
+            })
+        };
+        let lptim = |src: Option<LptimClkSource>| -> Result<(LptimClkSource, u32), ClockError> {
+            let src = src.unwrap_or(LptimClkSource::PCLK);
+            Ok((src, match src {
+                LptimClkSource::PCLK => pclk1,
+                LptimClkSource::LSI => lsi.ok_or(ClockError::SourceDisabled(Source::LSI))?,
+                LptimClkSource::HSI16 => HSI,
+                LptimClkSource::LSE => lse.ok_or(ClockError::SourceDisabled(Source::LSE))?,
+            }))
+        };
+        let sai = |src: Option<SaiClkSource>| -> Result<(SaiClkSource, Option<u32>), ClockError> {
+            match src {
+                // keep the reset value, which may not be running
+                None => Ok((SaiClkSource::PLLSAI1P, pllsai1clk)),
+                Some(src) => Ok((src, match src {
+                    SaiClkSource::PLLSAI1P => Some(pllsai1clk.ok_or(ClockError::SourceDisabled(Source::PLLSAI1))?),
+                    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+                    SaiClkSource::PLLSAI2P => Some(pllsai2clk.ok_or(ClockError::SourceDisabled(Source::PLLSAI2))?),
+                    SaiClkSource::PLLP => Some(pllsai3clk.ok_or(ClockError::SourceDisabled(Source::PLL))?),
+                    SaiClkSource::External => None,
+                })),
+            }
+        };
+
+        let (usart1_sel, usart1) = usart(sel.usart1, pclk2)?;
+        let (usart2_sel, usart2) = usart(sel.usart2, pclk1)?;
+        let (usart3_sel, usart3) = usart(sel.usart3, pclk1)?;
+        #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+        let (uart4_sel, uart4) = usart(sel.uart4, pclk1)?;
+        #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+        let (uart5_sel, uart5) = usart(sel.uart5, pclk1)?;
+        let (lpuart1_sel, lpuart1) = usart(sel.lpuart1, pclk1)?;
+        let (i2c1_sel, i2c1) = i2c(sel.i2c1);
+        let (i2c2_sel, i2c2) = i2c(sel.i2c2);
+        let (i2c3_sel, i2c3) = i2c(sel.i2c3);
+        let (lptim1_sel, lptim1) = lptim(sel.lptim1)?;
+        let (lptim2_sel, lptim2) = lptim(sel.lptim2)?;
+        let (sai1_sel, sai1) = sai(sel.sai1)?;
+        #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+        let (sai2_sel, sai2) = sai(sel.sai2)?;
+
+        let (adc_sel, adc) = match sel.adc.unwrap_or(AdcClkSource::NoClock) {
+            AdcClkSource::NoClock => (AdcClkSource::NoClock, None),
+            AdcClkSource::PLLSAI1R => (
+                AdcClkSource::PLLSAI1R,
+                Some(plladc1clk.ok_or(ClockError::SourceDisabled(Source::PLLSAI1))?),
+            ),
+            #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+            AdcClkSource::PLLSAI2R => (
+                AdcClkSource::PLLSAI2R,
+                Some(plladc2clk.ok_or(ClockError::SourceDisabled(Source::PLLSAI2))?),
+            ),
+            AdcClkSource::SYSCLK => (AdcClkSource::SYSCLK, Some(sysclk)),
+        };
+
+        let (clk48_sel, clk48) = match sel.clk48 {
+            None if msi_range == Some(MsiFreq::RANGE48M) => (Clk48Source::MSI, msi),
+            None => (Clk48Source::HSI48, hsi48),
+            Some(Clk48Source::HSI48) => (
+                Clk48Source::HSI48,
+                Some(hsi48.ok_or(ClockError::SourceDisabled(Source::HSI48))?),
+            ),
+            Some(Clk48Source::PLLSAI1Q) => (
+                Clk48Source::PLLSAI1Q,
+                Some(pll48m2clk.ok_or(ClockError::SourceDisabled(Source::PLLSAI1))?),
+            ),
+            Some(Clk48Source::PLLQ) => (
+                Clk48Source::PLLQ,
+                Some(pll48m1clk.ok_or(ClockError::SourceDisabled(Source::PLL))?),
+            ),
+            Some(Clk48Source::MSI) => (
+                Clk48Source::MSI,
+                Some(msi.ok_or(ClockError::SourceDisabled(Source::MSI))?),
+            ),
+        };
+        // USB and RNG need 48 MHz within 0.25 %
+        if let Some(clk48) = clk48 {
+            if (i64::from(clk48) - 48_000_000).abs() > CLK48_TOLERANCE {
+                return Err(ClockError::Clk48Inaccurate);
+            }
+        }
+
+        let hsi16_kernel = [usart1_sel, usart2_sel, usart3_sel, lpuart1_sel]
+            .iter()
+            .any(|&src| src == UsartClkSource::HSI16)
+            || [i2c1_sel, i2c2_sel, i2c3_sel].iter().any(|&src| src == I2cClkSource::HSI16)
+            || [lptim1_sel, lptim2_sel].iter().any(|&src| src == LptimClkSource::HSI16);
+        #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+        let hsi16_kernel = hsi16_kernel
+            || uart4_sel == UsartClkSource::HSI16
+            || uart5_sel == UsartClkSource::HSI16;
+
+        Ok(ClockPlan {
+            ccipr: CciprBits {
+                usart1: usart1_sel,
+                usart2: usart2_sel,
+                usart3: usart3_sel,
+                #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+                uart4: uart4_sel,
+                #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+                uart5: uart5_sel,
+                lpuart1: lpuart1_sel,
+                i2c1: i2c1_sel,
+                i2c2: i2c2_sel,
+                i2c3: i2c3_sel,
+                lptim1: lptim1_sel,
+                lptim2: lptim2_sel,
+                adc: adc_sel,
+                clk48: clk48_sel,
+                sai1: sai1_sel,
+                #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+                sai2: sai2_sel,
+            },
+            kernel: KernelClocks {
+                usart1,
+                usart2,
+                usart3,
+                #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+                uart4,
+                #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+                uart5,
+                lpuart1,
+                i2c1,
+                i2c2,
+                i2c3,
+                lptim1,
+                lptim2,
+                adc,
+                clk48,
+                sai1,
+                #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+                sai2,
+            },
+            hsi16_kernel,
+            pll_source,
+            pll_m,
+            msi: msi_range,
+            pllconf,
+            pllsai3clk,
+            pll48m1clk,
+            pllsai1clk,
+            pll48m2clk,
+            plladc1clk,
+            #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+            pllsai2clk,
+            #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+            plladc2clk,
+            sysclk_src,
+            sysclk,
+            hclk,
+            hpre_bits,
+            pclk1,
+            ppre1_bits,
+            ppre1,
+            pclk2,
+            ppre2_bits,
+            ppre2,
+            latency: flash_latency(range, hclk),
+        })
+    }
+
+}
+
+/// The validated clock tree, computed from a `CFGR` before it is applied
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct ClockPlan {
+    pll_source: PllSource,
+    /// Shared M divider, `None` if all PLLs are off
+    pll_m: Option<u8>,
+    /// MSI range to program, `None` leaves MSI untouched
+    msi: Option<MsiFreq>,
+    pllconf: Option<PllConfig>,
+    pllsai3clk: Option<u32>,
+    pll48m1clk: Option<u32>,
+    pllsai1clk: Option<u32>,
+    pll48m2clk: Option<u32>,
+    plladc1clk: Option<u32>,
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    pllsai2clk: Option<u32>,
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    plladc2clk: Option<u32>,
+    sysclk_src: SysClkSource,
+    sysclk: u32,
+    hclk: u32,
+    hpre_bits: u8,
+    pclk1: u32,
+    ppre1_bits: u8,
+    ppre1: u8,
+    pclk2: u32,
+    ppre2_bits: u8,
+    ppre2: u8,
+    latency: u8,
+    ccipr: CciprBits,
+    kernel: KernelClocks,
+    /// HSI16 feeds a peripheral kernel clock
+    hsi16_kernel: bool,
+}
+
+/// Resolved RCC_CCIPR selection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct CciprBits {
+    usart1: UsartClkSource,
+    usart2: UsartClkSource,
+    usart3: UsartClkSource,
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    uart4: UsartClkSource,
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    uart5: UsartClkSource,
+    lpuart1: UsartClkSource,
+    i2c1: I2cClkSource,
+    i2c2: I2cClkSource,
+    i2c3: I2cClkSource,
+    lptim1: LptimClkSource,
+    lptim2: LptimClkSource,
+    adc: AdcClkSource,
+    clk48: Clk48Source,
+    sai1: SaiClkSource,
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    sai2: SaiClkSource,
+}
+
+
+// Number of polling iterations before an oscillator is declared dead. HSE
+// crystals may take a few milliseconds to start up.
+pub(crate) const READY_TIMEOUT: u32 = 50_000;
+const HSE_TIMEOUT: u32 = 500_000;
+// LSE crystals may take up to two seconds to start up
+pub(crate) const LSE_TIMEOUT: u32 = 10_000_000;
+
+/// Polls `ready` until it returns `true`, at most `timeout` times, failing
+/// with `error` otherwise
+pub(crate) fn wait_for<F>(ready: F, error: ClockError, timeout: u32) -> Result<(), ClockError>
+where
+    F: Fn() -> bool,
+{
+    for _ in 0..timeout {
+        if ready() {
+            return Ok(());
+        }
+    }
+    Err(error)
+}
+
+/// AHB prescaler bits and divisor for `hclk` derived from `sysclk`
+fn hpre(sysclk: u32, hclk: u32) -> Result<(u8, u32), ClockError> {
+    if hclk == 0 {
+        return Err(ClockError::InvalidPrescaler);
+    }
+    Ok(match sysclk / hclk {
+        // From p 194 in RM0394
+        0 => return Err(ClockError::InvalidPrescaler),
+        1 => (0b0000, 1),
+        2 => (0b1000, 2),
+        3..=5 => (0b1001, 4),
+        6..=11 => (0b1010, 8),
+        12..=39 => (0b1011, 16),
+        40..=95 => (0b1100, 64),
+        96..=191 => (0b1101, 128),
+        192..=383 => (0b1110, 256),
+        _ => (0b1111, 512),
+    })
+}
+
+/// APB prescaler bits and divisor for `pclk` derived from `hclk`
+fn ppre(hclk: u32, pclk: u32) -> Result<(u8, u8), ClockError> {
+    if pclk == 0 {
+        return Err(ClockError::InvalidPrescaler);
+    }
+    Ok(match hclk / pclk {
+        // From p 194 in RM0394
+        0 => return Err(ClockError::InvalidPrescaler),
+        1 => (0b000, 1),
+        2 => (0b100, 2),
+        3..=5 => (0b101, 4),
+        6..=11 => (0b110, 8),
+        _ => (0b111, 16)
+    })
+}
+
+/// Timer clock of an APB bus running at `pclk`, divided by `ppre` from HCLK
+///
+/// The timers run at twice the bus frequency unless the bus is undivided
+/// (RM0394 6.2.14).
+fn timclk(pclk: u32, ppre: u8) -> u32 {
+    if ppre == 1 {
+        pclk
+    } else {
+        2 * pclk
+    }
+}
+
+/// Programs the flash wait states, taking effect once read back (RM0394 3.3.3)
+fn set_flash_latency(latency: u8) -> Result<(), ClockError> {
+    let flash = unsafe { &*FLASH::ptr() };
+    flash.acr.modify(|_, w| unsafe { w.latency().bits(latency) });
+    wait_for(
+        || flash.acr.read().latency().bits() == latency,
+        ClockError::FlashLatencyTimeout,
+        READY_TIMEOUT,
+    )
+}
+
+/// Flash wait states required at `hclk` in `range` (RM0394 3.3.3, table 9)
+fn flash_latency(range: VoltageRange, hclk: u32) -> u8 {
+    let thresholds: &[u32] = match range {
+        VoltageRange::Range1 => &[16_000_000, 32_000_000, 48_000_000, 64_000_000],
+        VoltageRange::Range2 => &[6_000_000, 12_000_000, 18_000_000],
+    };
+    thresholds.iter().take_while(|&&max| hclk > max).count() as u8
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Pll Configuration - Calculation = ((SourceClk / m) * n) / r
+///
+/// The fields hold the raw register values: the input is divided by `m + 1`
+/// and PLLCLK by `2 * (r + 1)`.
+pub struct PllConfig {
+    /// Main PLL Division factor
+    pub m: u8,
+    /// Main Pll Multiplication factor
+    pub n: u8,
+    /// Main PLL division factor for PLLCLK (system clock)
+    pub r: u8,
+}
+
+impl PllConfig {
+    /// Returns the VCO output frequency for the PLL input frequency `input`
+    pub fn vco(&self, input: u32) -> u32 {
+        (u64::from(input) * u64::from(self.n) / (u64::from(self.m) + 1)) as u32
+    }
+
+    /// Returns the PLLCLK frequency for the PLL input frequency `input`
+    pub fn pllclk(&self, input: u32) -> u32 {
+        self.vco(input) / (2 * (u32(self.r) + 1))
+    }
+}
+
+/// Division factor of a PLL P output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PllPDiv {
+    /// Divide the VCO output by 7
+    Div7 = 0,
+    /// Divide the VCO output by 17
+    Div17 = 1,
+}
+
+impl PllPDiv {
+    fn divisor(self) -> u32 {
+        match self {
+            PllPDiv::Div7 => 7,
+            PllPDiv::Div17 => 17,
+        }
+    }
+}
+
+/// Division factor of a PLL Q or R output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PllDivider {
+    /// Divide the VCO output by 2
+    Div2 = 0b00,
+    /// Divide the VCO output by 4
+    Div4 = 0b01,
+    /// Divide the VCO output by 6
+    Div6 = 0b10,
+    /// Divide the VCO output by 8
+    Div8 = 0b11,
+}
+
+impl PllDivider {
+    fn divisor(self) -> u32 {
+        2 * (self as u32 + 1)
+    }
+}
+
+/// PLLSAI1 Configuration - Calculation = ((SourceClk / m) * n) / (p, q or r)
+///
+/// The input and M divider are those of the main PLL.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PllSai1Config {
+    /// Multiplication factor for the VCO, 8 to 86
+    pub n: u8,
+    /// Division factor for PLLSAI1CLK (SAI), output disabled if `None`
+    pub p: Option<PllPDiv>,
+    /// Division factor for PLL48M2CLK (USB, RNG, SDMMC), output disabled if `None`
+    pub q: Option<PllDivider>,
+    /// Division factor for PLLADC1CLK (ADC), output disabled if `None`
+    pub r: Option<PllDivider>,
+}
+
+/// PLLSAI2 Configuration - Calculation = ((SourceClk / m) * n) / (p or r)
+///
+/// The input and M divider are those of the main PLL.
+#[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PllSai2Config {
+    /// Multiplication factor for the VCO, 8 to 86
+    pub n: u8,
+    /// Division factor for PLLSAI2CLK (SAI), output disabled if `None`
+    pub p: Option<PllPDiv>,
+    /// Division factor for PLLADC2CLK (ADC), output disabled if `None`
+    pub r: Option<PllDivider>,
+}
+
+/// Kernel clock of a USART or LPUART (RCC_CCIPR USARTxSEL, LPUART1SEL)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UsartClkSource {
+    /// APB clock the peripheral is attached to (reset value)
+    PCLK = 0b00,
+    /// System clock
+    SYSCLK = 0b01,
+    /// HSI16, which keeps the peripheral clocked in Stop mode
+    HSI16 = 0b10,
+    /// LSE, for low baud rates in Stop mode
+    LSE = 0b11,
+}
+
+/// Kernel clock of an I2C (RCC_CCIPR I2CxSEL)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum I2cClkSource {
+    /// APB1 clock (reset value)
+    PCLK = 0b00,
+    /// System clock
+    SYSCLK = 0b01,
+    /// HSI16, which keeps the peripheral clocked in Stop mode
+    HSI16 = 0b10,
+}
+
+/// Kernel clock of a low-power timer (RCC_CCIPR LPTIMxSEL)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LptimClkSource {
+    /// APB1 clock (reset value)
+    PCLK = 0b00,
+    /// LSI
+    LSI = 0b01,
+    /// HSI16
+    HSI16 = 0b10,
+    /// LSE
+    LSE = 0b11,
+}
+
+/// Kernel clock of the ADCs (RCC_CCIPR ADCSEL)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdcClkSource {
+    /// No clock (reset value)
+    NoClock = 0b00,
+    /// PLLSAI1 R output (PLLADC1CLK)
+    PLLSAI1R = 0b01,
+    /// PLLSAI2 R output (PLLADC2CLK)
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    PLLSAI2R = 0b10,
+    /// System clock
+    SYSCLK = 0b11,
+}
+
+/// 48 MHz clock for USB, RNG and SDMMC (RCC_CCIPR CLK48SEL)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Clk48Source {
+    /// HSI48, not available on all devices
+    HSI48 = 0b00,
+    /// PLLSAI1 Q output (PLL48M2CLK)
+    PLLSAI1Q = 0b01,
+    /// Main PLL Q output (PLL48M1CLK)
+    PLLQ = 0b10,
+    /// MSI, which should run at 48 MHz
+    MSI = 0b11,
+}
+
+/// Kernel clock of a SAI (RCC_CCIPR SAIxSEL)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SaiClkSource {
+    /// PLLSAI1 P output (PLLSAI1CLK, reset value)
+    PLLSAI1P = 0b00,
+    /// PLLSAI2 P output (PLLSAI2CLK)
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    PLLSAI2P = 0b01,
+    /// Main PLL P output (PLLSAI3CLK)
+    PLLP = 0b10,
+    /// External clock on SAI_EXTCLK, not reported in `Clocks`
+    External = 0b11,
+}
+
+/// Kernel clock selection, `None` keeps the reset value
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct KernelClockSelection {
+    usart1: Option<UsartClkSource>,
+    usart2: Option<UsartClkSource>,
+    usart3: Option<UsartClkSource>,
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    uart4: Option<UsartClkSource>,
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    uart5: Option<UsartClkSource>,
+    lpuart1: Option<UsartClkSource>,
+    i2c1: Option<I2cClkSource>,
+    i2c2: Option<I2cClkSource>,
+    i2c3: Option<I2cClkSource>,
+    lptim1: Option<LptimClkSource>,
+    lptim2: Option<LptimClkSource>,
+    adc: Option<AdcClkSource>,
+    clk48: Option<Clk48Source>,
+    sai1: Option<SaiClkSource>,
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    sai2: Option<SaiClkSource>,
+}
+
+/// Kernel clock frequencies of the peripherals
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct KernelClocks {
+    usart1: u32,
+    usart2: u32,
+    usart3: u32,
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    uart4: u32,
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    uart5: u32,
+    lpuart1: u32,
+    i2c1: u32,
+    i2c2: u32,
+    i2c3: u32,
+    lptim1: u32,
+    lptim2: u32,
+    adc: Option<u32>,
+    clk48: Option<u32>,
+    sai1: Option<u32>,
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    sai2: Option<u32>,
+}
+
+/// Clock configuration error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockError {
+    /// The requested system clock exceeds 80 MHz, or 26 MHz in voltage Range 2
+    SysclkTooHigh,
+    /// No PLL setting produces the requested frequency
+    PllUnreachable,
+    /// A bus frequency cannot be derived from its parent clock
+    InvalidPrescaler,
+    /// A clock source is required by the configuration but not enabled
+    SourceDisabled(Source),
+    /// A clock source did not become ready in time
+    OscillatorTimeout(Source),
+    /// A clock source runs faster than the voltage range allows
+    VoltageRangeExceeded(Source),
+    /// The flash did not accept the new number of wait states in time
+    FlashLatencyTimeout,
+    /// The regulator did not settle in the new voltage range, or leave
+    /// low-power run mode, in time
+    VoltageScalingTimeout,
+    /// The 48 MHz clock is more than 0.25 % off
+    Clk48Inaccurate,
+}
+
+/// Clock source, as reported by `ClockError`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// Multi-speed internal clock
+    MSI,
+    /// High-speed internal 16 MHz clock
+    HSI16,
+    /// High-speed external clock
+    HSE,
+    /// High-speed internal 48 MHz clock
+    HSI48,
+    /// Low-speed internal 32 kHz clock
+    LSI,
+    /// Low-speed external 32.768 kHz clock
+    LSE,
+    /// Main PLL
+    PLL,
+    /// PLLSAI1
+    PLLSAI1,
+    /// PLLSAI2
+    PLLSAI2,
+}
+
+impl From<SysClkSource> for Source {
+    fn from(src: SysClkSource) -> Source {
+        match src {
+            SysClkSource::MSI => Source::MSI,
+            SysClkSource::HSI16 => Source::HSI16,
+            SysClkSource::HSE => Source::HSE,
+            SysClkSource::PLL => Source::PLL,
+        }
+    }
+}
+
+// Main PLL limits, from RM0394 6.4.4 and the STM32L4 datasheets
+const PLL_VCO_IN_MIN: u32 = 4_000_000;
+const PLL_VCO_IN_MAX: u32 = 16_000_000;
+const PLL_VCO_OUT_MIN: u32 = 64_000_000;
+/// Deviation of the 48 MHz clock allowed by USB, 0.25 %
+const CLK48_TOLERANCE: i64 = 120_000;
+
+/// Frequency limits of a voltage range, from the STM32L4 datasheets
+struct RangeLimits {
+    sysclk: u32,
+    /// PLL VCO output, all PLLs
+    vco: u32,
+    /// P, Q and R outputs, all PLLs
+    pllclk: u32,
+    hse: u32,
+    msi: u32,
+}
+
+impl RangeLimits {
+    fn of(range: VoltageRange) -> Self {
+        match range {
+            VoltageRange::Range1 => RangeLimits {
+                sysclk: 80_000_000,
+                vco: 344_000_000,
+                pllclk: 80_000_000,
+                hse: 48_000_000,
+                msi: 48_000_000,
+            },
+            VoltageRange::Range2 => RangeLimits {
+                sysclk: 26_000_000,
+                vco: 128_000_000,
+                pllclk: 26_000_000,
+                hse: 26_000_000,
+                msi: 24_000_000,
+            },
+        }
+    }
+}
+
+/// Smallest M divider (raw register value) bringing `input` within the VCO input range
+fn pll_m_for(input: u32) -> Result<u8, ClockError> {
+    (0..8u8)
+        .find(|&m| {
+            let vco_in = input / (u32(m) + 1);
+            (PLL_VCO_IN_MIN..=PLL_VCO_IN_MAX).contains(&vco_in)
+        })
+        .ok_or(ClockError::PllUnreachable)
+}
+
+/// VCO frequency of a PLLSAI fed with `input`, divided by `m + 1` and multiplied by `n`
+fn sai_vco(input: u32, m: u8, n: u8, vco_max: u32) -> Result<u32, ClockError> {
+    if !(8..=86).contains(&n) {
+        return Err(ClockError::PllUnreachable);
+    }
+    let vco = (u64::from(input) * u64::from(n) / (u64::from(m) + 1)) as u32;
+    if !(PLL_VCO_OUT_MIN..=vco_max).contains(&vco) {
+        return Err(ClockError::PllUnreachable);
+    }
+    Ok(vco)
+}
+
+/// Divides `vco` down to an output of `pll`, within the maximum of `limits`
+fn pll_output(vco: u32, divisor: u32, limits: &RangeLimits, pll: Source) -> Result<u32, ClockError> {
+    let out = vco / divisor;
+    if out > limits.pllclk {
+        return Err(ClockError::VoltageRangeExceeded(pll));
+    }
+    Ok(out)
+}
+
+/// Checks that `conf` keeps the main PLL, fed with `input`, within `limits`
+fn check_pll(input: u32, conf: &PllConfig, limits: &RangeLimits) -> Result<(), ClockError> {
+    let vco_in = input / (u32(conf.m) + 1);
+    let valid = conf.m < 8
+        && conf.r < 4
+        && (8..=86).contains(&conf.n)
+        && (PLL_VCO_IN_MIN..=PLL_VCO_IN_MAX).contains(&vco_in)
+        && (PLL_VCO_OUT_MIN..=limits.vco).contains(&conf.vco(input))
+        && conf.pllclk(input) <= limits.pllclk;
+    if valid {
+        Ok(())
+    } else {
+        Err(ClockError::PllUnreachable)
+    }
+}
+
+/// Finds the PLL configuration producing the PLLCLK closest to `target`
+///
+/// All valid M (1-8), N (8-86) and R (2, 4, 6, 8) are searched, keeping the VCO
+/// input and output within their limits. An exact match is always preferred;
+/// otherwise the result may be off by at most 0.1 %, beyond which the target is
+/// considered unreachable.
+///
+/// The limits are those of voltage Range 1.
+pub fn solve_pll(input: u32, target: u32) -> Result<PllConfig, ClockError> {
+    solve_pll_within(input, target, &RangeLimits::of(VoltageRange::Range1))
+}
+
+fn solve_pll_within(input: u32, target: u32, limits: &RangeLimits) -> Result<PllConfig, ClockError> {
+    if target == 0 || target > limits.pllclk {
+        return Err(ClockError::PllUnreachable);
+    }
+
+    let mut best: Option<(PllConfig, u32)> = None;
+    for m in 0..8u8 {
+        let vco_in = input / (u32(m) + 1);
+        if !(PLL_VCO_IN_MIN..=PLL_VCO_IN_MAX).contains(&vco_in) {
+            continue;
+        }
+        for r in 0..4u8 {
+            for n in 8..=86u8 {
+                let conf = PllConfig { m, n, r };
+                let vco = conf.vco(input);
+                if !(PLL_VCO_OUT_MIN..=limits.vco).contains(&vco) {
+                    continue;
+                }
+                let pllclk = conf.pllclk(input);
+                if pllclk > limits.pllclk {
+                    continue;
+                }
+                let error = if pllclk > target { pllclk - target } else { target - pllclk };
+                match best {
+                    Some((_, best_error)) if best_error <= error => {}
+                    _ => best = Some((conf, error)),
+                }
+            }
+        }
+    }
+
+    match best {
+        Some((conf, error)) if u64::from(error) * 1000 <= u64::from(target) => Ok(conf),
+        _ => Err(ClockError::PllUnreachable),
+    }
+}
 
 /// Frozen clock frequencies
 ///
@@ -550,16 +2137,31 @@ pub struct PllConfig {
 #[derive(Clone, Copy, Debug)]
 pub struct Clocks {
     hclk: Hertz,
+    hse: Option<Hertz>,
     hsi48: bool,
+    lse: bool,
     msi: Option<MsiFreq>,
     lsi: bool,
+    msi_pll: bool,
     pclk1: Hertz,
     pclk2: Hertz,
-    // TODO remove `allow`
-    #[allow(dead_code)]
     ppre1: u8,
     ppre2: u8,
     sysclk: Hertz,
+    sysclk_src: SysClkSource,
+    pll_source: Option<PllSource>,
+    pllsai3clk: Option<Hertz>,
+    pll48m1clk: Option<Hertz>,
+    pllsai1clk: Option<Hertz>,
+    pll48m2clk: Option<Hertz>,
+    plladc1clk: Option<Hertz>,
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    pllsai2clk: Option<Hertz>,
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    plladc2clk: Option<Hertz>,
+    kernel: KernelClocks,
+    ccipr: CciprBits,
+    voltage_range: VoltageRange,
 }
 
 impl Clocks {
@@ -568,6 +2170,11 @@ impl Clocks {
         self.hclk
     }
 
+    /// Returns the frequency of the HSE, if enabled
+    pub fn hse(&self) -> Option<Hertz> {
+        self.hse
+    }
+
     /// Returns status of HSI48
     pub fn hsi48(&self) -> bool {
         self.hsi48
@@ -583,6 +2190,16 @@ impl Clocks {
         self.lsi
     }
 
+    /// Returns status of the LSE
+    pub fn lse(&self) -> bool {
+        self.lse
+    }
+
+    /// Returns whether MSI is trimmed against LSE (MSI PLL-mode)
+    pub fn msi_pll_mode(&self) -> bool {
+        self.msi_pll
+    }
+
     /// Returns the frequency of the APB1
     pub fn pclk1(&self) -> Hertz {
         self.pclk1
@@ -593,19 +2210,427 @@ impl Clocks {
         self.pclk2
     }
 
-    // TODO remove `allow`
-    #[allow(dead_code)]
-    pub(crate) fn ppre1(&self) -> u8 {
-        self.ppre1
+    /// Returns the kernel clock of the timers on APB1 (TIM2-7, LPTIM excluded)
+    pub fn timclk1(&self) -> Hertz {
+        Hertz(timclk(self.pclk1.0, self.ppre1))
     }
-    // TODO remove `allow`
-    #[allow(dead_code)]
-    pub(crate) fn ppre2(&self) -> u8 {
-        self.ppre2
+
+    /// Returns the kernel clock of the timers on APB2 (TIM1, TIM8, TIM15-17)
+    pub fn timclk2(&self) -> Hertz {
+        Hertz(timclk(self.pclk2.0, self.ppre2))
     }
 
     /// Returns the system (core) frequency
     pub fn sysclk(&self) -> Hertz {
         self.sysclk
     }
+
+    /// Returns the voltage range the clocks were validated against
+    pub fn voltage_range(&self) -> VoltageRange {
+        self.voltage_range
+    }
+
+    /// Returns the source of the system clock
+    pub fn sysclk_source(&self) -> SysClkSource {
+        self.sysclk_src
+    }
+
+    /// Returns the input shared by all PLLs, if any PLL is running
+    pub fn pll_source(&self) -> Option<PllSource> {
+        self.pll_source
+    }
+
+    /// Returns the frequency of the main PLL P output (PLLSAI3CLK), if enabled
+    pub fn pllsai3clk(&self) -> Option<Hertz> {
+        self.pllsai3clk
+    }
+
+    /// Returns the frequency of the main PLL Q output (PLL48M1CLK), if enabled
+    pub fn pll48m1clk(&self) -> Option<Hertz> {
+        self.pll48m1clk
+    }
+
+    /// Returns the frequency of the PLLSAI1 P output (PLLSAI1CLK), if enabled
+    pub fn pllsai1clk(&self) -> Option<Hertz> {
+        self.pllsai1clk
+    }
+
+    /// Returns the frequency of the PLLSAI1 Q output (PLL48M2CLK), if enabled
+    pub fn pll48m2clk(&self) -> Option<Hertz> {
+        self.pll48m2clk
+    }
+
+    /// Returns the frequency of the PLLSAI1 R output (PLLADC1CLK), if enabled
+    pub fn plladc1clk(&self) -> Option<Hertz> {
+        self.plladc1clk
+    }
+
+    /// Returns the frequency of the PLLSAI2 P output (PLLSAI2CLK), if enabled
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    pub fn pllsai2clk(&self) -> Option<Hertz> {
+        self.pllsai2clk
+    }
+
+    /// Returns the frequency of the PLLSAI2 R output (PLLADC2CLK), if enabled
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    pub fn plladc2clk(&self) -> Option<Hertz> {
+        self.plladc2clk
+    }
+
+    /// Returns the kernel clock frequency of USART1
+    pub fn usart1clk(&self) -> Hertz {
+        Hertz(self.kernel.usart1)
+    }
+
+    /// Returns the kernel clock frequency of USART2
+    pub fn usart2clk(&self) -> Hertz {
+        Hertz(self.kernel.usart2)
+    }
+
+    /// Returns the kernel clock frequency of USART3
+    pub fn usart3clk(&self) -> Hertz {
+        Hertz(self.kernel.usart3)
+    }
+
+    /// Returns the kernel clock frequency of UART4
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    pub fn uart4clk(&self) -> Hertz {
+        Hertz(self.kernel.uart4)
+    }
+
+    /// Returns the kernel clock frequency of UART5
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    pub fn uart5clk(&self) -> Hertz {
+        Hertz(self.kernel.uart5)
+    }
+
+    /// Returns the kernel clock frequency of LPUART1
+    pub fn lpuart1clk(&self) -> Hertz {
+        Hertz(self.kernel.lpuart1)
+    }
+
+    /// Returns the kernel clock frequency of I2C1
+    pub fn i2c1clk(&self) -> Hertz {
+        Hertz(self.kernel.i2c1)
+    }
+
+    /// Returns the kernel clock frequency of I2C2
+    pub fn i2c2clk(&self) -> Hertz {
+        Hertz(self.kernel.i2c2)
+    }
+
+    /// Returns the kernel clock frequency of I2C3
+    pub fn i2c3clk(&self) -> Hertz {
+        Hertz(self.kernel.i2c3)
+    }
+
+    /// Returns the kernel clock frequency of LPTIM1
+    pub fn lptim1clk(&self) -> Hertz {
+        Hertz(self.kernel.lptim1)
+    }
+
+    /// Returns the kernel clock frequency of LPTIM2
+    pub fn lptim2clk(&self) -> Hertz {
+        Hertz(self.kernel.lptim2)
+    }
+
+    /// Returns the kernel clock frequency of the ADCs, if clocked from RCC_CCIPR
+    pub fn adcclk(&self) -> Option<Hertz> {
+        self.kernel.adc.map(Hertz)
+    }
+
+    /// Returns the frequency of the 48 MHz clock (USB, RNG, SDMMC), if running
+    pub fn clk48(&self) -> Option<Hertz> {
+        self.kernel.clk48.map(Hertz)
+    }
+
+    /// Returns the kernel clock frequency of SAI1, if running from an internal clock
+    pub fn sai1clk(&self) -> Option<Hertz> {
+        self.kernel.sai1.map(Hertz)
+    }
+
+    /// Returns the kernel clock frequency of SAI2, if running from an internal clock
+    #[cfg(any(feature = "stm32l4x5", feature = "stm32l4x6"))]
+    pub fn sai2clk(&self) -> Option<Hertz> {
+        self.kernel.sai2.map(Hertz)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::U32Ext;
+
+    const HSE_8M: u32 = 8_000_000;
+    const MSI_4M: u32 = 4_000_000;
+    const MSI_48M: u32 = 48_000_000;
+
+    fn assert_within_limits(input: u32, conf: PllConfig, limits: &RangeLimits) {
+        let vco_in = input / (u32::from(conf.m) + 1);
+        assert!((PLL_VCO_IN_MIN..=PLL_VCO_IN_MAX).contains(&vco_in), "{:?}", conf);
+        assert!((8..=86).contains(&conf.n), "{:?}", conf);
+        assert!(conf.r < 4, "{:?}", conf);
+        assert!((PLL_VCO_OUT_MIN..=limits.vco).contains(&conf.vco(input)), "{:?}", conf);
+        assert!(conf.pllclk(input) <= limits.pllclk, "{:?}", conf);
+    }
+
+    #[test]
+    fn solve_pll_exact() {
+        let limits = RangeLimits::of(VoltageRange::Range1);
+        for &input in &[HSI, MSI_4M, MSI_48M, HSE_8M] {
+            for &target in &[80_000_000, 64_000_000, 48_000_000, 32_000_000, 16_000_000] {
+                let conf = solve_pll(input, target).unwrap();
+                assert_eq!(conf.pllclk(input), target, "{} Hz from {} Hz", target, input);
+                assert_within_limits(input, conf, &limits);
+            }
+        }
+    }
+
+    #[test]
+    fn solve_pll_80mhz_from_hse() {
+        let conf = solve_pll(HSE_8M, 80_000_000).unwrap();
+        assert_eq!(conf.pllclk(HSE_8M), 80_000_000);
+    }
+
+    #[test]
+    fn solve_pll_close_enough() {
+        // 80 MHz is 0.0125 % away
+        let conf = solve_pll(HSI, 79_990_000).unwrap();
+        assert_eq!(conf.pllclk(HSI), 80_000_000);
+
+        // every target in range is either solved within 0.1 % or rejected
+        let limits = RangeLimits::of(VoltageRange::Range1);
+        for &input in &[HSI, MSI_4M, HSE_8M] {
+            for target in (8_000_000..=80_000_000).step_by(333_333) {
+                if let Ok(conf) = solve_pll(input, target) {
+                    let pllclk = conf.pllclk(input);
+                    let error = if pllclk > target { pllclk - target } else { target - pllclk };
+                    assert!(u64::from(error) * 1000 <= u64::from(target));
+                    assert_within_limits(input, conf, &limits);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn solve_pll_too_far() {
+        // the slowest PLLCLK is 64 MHz / 8
+        assert_eq!(solve_pll(HSI, 7_000_000), Err(ClockError::PllUnreachable));
+        assert_eq!(solve_pll(HSI, 0), Err(ClockError::PllUnreachable));
+    }
+
+    #[test]
+    fn solve_pll_above_limit() {
+        assert_eq!(solve_pll(HSI, 80_000_001), Err(ClockError::PllUnreachable));
+        assert_eq!(solve_pll(HSE_8M, 120_000_000), Err(ClockError::PllUnreachable));
+        let range2 = RangeLimits::of(VoltageRange::Range2);
+        assert_eq!(
+            solve_pll_within(HSI, 48_000_000, &range2),
+            Err(ClockError::PllUnreachable)
+        );
+    }
+
+    #[test]
+    fn solve_pll_no_valid_m() {
+        // below 4 MHz even with M = 1, above 16 MHz even with M = 8
+        assert_eq!(solve_pll(2_000_000, 16_000_000), Err(ClockError::PllUnreachable));
+        assert_eq!(solve_pll(200_000_000, 16_000_000), Err(ClockError::PllUnreachable));
+        assert_eq!(pll_m_for(2_000_000), Err(ClockError::PllUnreachable));
+        assert_eq!(pll_m_for(HSI), Ok(0));
+        assert_eq!(pll_m_for(MSI_48M), Ok(2));
+    }
+
+    #[test]
+    fn solve_pll_vco_limits() {
+        // 48 MHz MSI must be divided down to at most 16 MHz
+        let conf = solve_pll(MSI_48M, 80_000_000).unwrap();
+        assert!(conf.m >= 2);
+
+        // Range 2 caps the VCO at 128 MHz
+        let range2 = RangeLimits::of(VoltageRange::Range2);
+        let conf = solve_pll_within(HSI, 26_000_000, &range2).unwrap();
+        assert_within_limits(HSI, conf, &range2);
+        assert_eq!(conf.pllclk(HSI), 26_000_000);
+    }
+
+    #[test]
+    fn sai_vco_limits() {
+        assert_eq!(sai_vco(HSI, 0, 8, 344_000_000), Ok(128_000_000));
+        assert_eq!(sai_vco(HSI, 0, 7, 344_000_000), Err(ClockError::PllUnreachable));
+        assert_eq!(sai_vco(HSI, 0, 87, 344_000_000), Err(ClockError::PllUnreachable));
+        // 16 MHz * 86 exceeds the Range 1 VCO maximum
+        assert_eq!(sai_vco(HSI, 0, 86, 344_000_000), Err(ClockError::PllUnreachable));
+        // 4 MHz * 8 is below the VCO minimum
+        assert_eq!(sai_vco(HSI, 3, 8, 344_000_000), Err(ClockError::PllUnreachable));
+    }
+
+    fn plan(cfgr: CFGR) -> Result<ClockPlan, ClockError> {
+        cfgr.plan(VoltageRange::Range1)
+    }
+
+    #[test]
+    fn plan_default_runs_from_hsi() {
+        let plan = plan(CFGR::default()).unwrap();
+        assert_eq!(plan.sysclk_src, SysClkSource::HSI16);
+        assert_eq!((plan.sysclk, plan.hclk, plan.pclk1, plan.pclk2), (HSI, HSI, HSI, HSI));
+        assert_eq!(plan.pll_m, None);
+        assert_eq!(plan.latency, 0);
+    }
+
+    #[test]
+    fn plan_sysclk_too_high() {
+        let cfgr = CFGR::default().sysclk(81.mhz());
+        assert_eq!(plan(cfgr), Err(ClockError::SysclkTooHigh));
+        let cfgr = CFGR::default().sysclk(32.mhz());
+        assert_eq!(cfgr.plan(VoltageRange::Range2), Err(ClockError::SysclkTooHigh));
+    }
+
+    #[test]
+    fn plan_invalid_prescaler() {
+        // bus clocks faster than their parent
+        let cfgr = CFGR::default().hclk(32.mhz());
+        assert_eq!(plan(cfgr), Err(ClockError::InvalidPrescaler));
+        let cfgr = CFGR::default().sysclk(80.mhz()).hclk(40.mhz()).pclk1(80.mhz());
+        assert_eq!(plan(cfgr), Err(ClockError::InvalidPrescaler));
+        let cfgr = CFGR::default().pclk2(0.hz());
+        assert_eq!(plan(cfgr), Err(ClockError::InvalidPrescaler));
+    }
+
+    #[test]
+    fn plan_pll_outputs() {
+        // VCO of 16 MHz * 20 = 320 MHz, PLLCLK of 80 MHz but PLL48M1CLK of 160 MHz
+        let conf = PllConfig { m: 0, n: 20, r: 1 };
+        let cfgr = CFGR::default().sysclk_with_pll(80.mhz(), conf).pll_q(PllDivider::Div2);
+        assert_eq!(plan(cfgr), Err(ClockError::VoltageRangeExceeded(Source::PLL)));
+
+        // PLL48M2CLK of 16 MHz * 13 / 4 = 52 MHz
+        let conf = PllConfig { m: 0, n: 10, r: 0 };
+        let sai1 = |n| PllSai1Config { n, p: None, q: Some(PllDivider::Div4), r: None };
+        let cfgr = CFGR::default()
+            .sysclk_with_pll(80.mhz(), conf)
+            .pllsai1(sai1(13))
+            .clk48_src(Clk48Source::PLLSAI1Q);
+        assert_eq!(plan(cfgr), Err(ClockError::Clk48Inaccurate));
+
+        // 16 MHz * 12 / 4 = 48 MHz
+        let cfgr = CFGR::default()
+            .sysclk_with_pll(80.mhz(), conf)
+            .pllsai1(sai1(12))
+            .clk48_src(Clk48Source::PLLSAI1Q);
+        assert_eq!(plan(cfgr).unwrap().kernel.clk48, Some(48_000_000));
+    }
+
+    #[test]
+    fn plan_source_disabled() {
+        let cfgr = CFGR::default().pll_q(PllDivider::Div2);
+        assert_eq!(plan(cfgr), Err(ClockError::SourceDisabled(Source::PLL)));
+        let cfgr = CFGR::default().pll_p(PllPDiv::Div7);
+        assert_eq!(plan(cfgr), Err(ClockError::SourceDisabled(Source::PLL)));
+        let cfgr = CFGR::default().msi(MsiFreq::RANGE48M).msi_pll_mode(true);
+        assert_eq!(plan(cfgr), Err(ClockError::SourceDisabled(Source::LSE)));
+        let cfgr = CFGR::default().pll_source(PllSource::HSE).sysclk(80.mhz());
+        assert_eq!(plan(cfgr), Err(ClockError::SourceDisabled(Source::HSE)));
+    }
+
+    #[test]
+    fn plan_prescalers() {
+        let cfgr = CFGR::default()
+            .sysclk(80.mhz())
+            .hclk(40.mhz())
+            .pclk1(10.mhz())
+            .pclk2(40.mhz());
+        let plan = plan(cfgr).unwrap();
+        assert_eq!(plan.sysclk_src, SysClkSource::PLL);
+        assert_eq!(plan.sysclk, 80_000_000);
+        assert_eq!((plan.hclk, plan.hpre_bits), (40_000_000, 0b1000));
+        assert_eq!((plan.pclk1, plan.ppre1_bits, plan.ppre1), (10_000_000, 0b101, 4));
+        assert_eq!((plan.pclk2, plan.ppre2_bits, plan.ppre2), (40_000_000, 0b000, 1));
+    }
+
+    #[test]
+    fn plan_latency() {
+        // wait states follow HCLK, not SYSCLK
+        let cases: [(u32, u32, u8); 5] = [(16, 16, 0), (80, 80, 4), (80, 40, 2), (48, 48, 2), (64, 64, 3)];
+        for &(sysclk, hclk, latency) in &cases {
+            let cfgr = CFGR::default().sysclk(sysclk.mhz()).hclk(hclk.mhz());
+            assert_eq!(plan(cfgr).unwrap().latency, latency, "{} MHz", hclk);
+        }
+
+        let cfgr = CFGR::default().sysclk(26.mhz());
+        assert_eq!(cfgr.plan(VoltageRange::Range2).unwrap().latency, 3);
+    }
+
+    #[test]
+    fn plan_msi_sysclk() {
+        let cfgr = CFGR::default().msi(MsiFreq::RANGE100K).sysclk(100.khz());
+        let low = plan(cfgr).unwrap();
+        assert_eq!(low.sysclk_src, SysClkSource::MSI);
+        assert_eq!((low.sysclk, low.pll_m, low.latency), (100_000, None, 0));
+
+        let cfgr = CFGR::default().msi(MsiFreq::RANGE48M).sysclk(48.mhz());
+        let high = plan(cfgr).unwrap();
+        assert_eq!(high.sysclk_src, SysClkSource::MSI);
+        assert_eq!(high.latency, 2);
+
+        // enabling MSI alone does not change the SYSCLK source
+        let cfgr = CFGR::default().msi(MsiFreq::RANGE4M);
+        assert_eq!(plan(cfgr).unwrap().sysclk_src, SysClkSource::HSI16);
+        let cfgr = CFGR::default().msi(MsiFreq::RANGE4M).sysclk(8.mhz());
+        assert_eq!(plan(cfgr).unwrap().sysclk_src, SysClkSource::PLL);
+    }
+
+    #[test]
+    fn plan_msi_pll_source() {
+        // MSI feeding the PLL is programmed even if its range was not given
+        let cfgr = CFGR::default().pll_source(PllSource::MSI).sysclk(80.mhz());
+        let fed = plan(cfgr).unwrap();
+        assert_eq!((fed.sysclk, fed.msi), (80_000_000, Some(MsiFreq::RANGE4M)));
+
+        // but left alone if no PLL runs
+        let cfgr = CFGR::default().pll_source(PllSource::MSI);
+        assert_eq!(plan(cfgr).unwrap().msi, None);
+    }
+
+    #[test]
+    fn plan_user_pll_config() {
+        // 16 MHz / 1 * 10 / 2 = 80 MHz
+        let conf = PllConfig { m: 0, n: 10, r: 0 };
+        let cfgr = CFGR::default().sysclk_with_pll(80.mhz(), conf);
+        assert_eq!(plan(cfgr).unwrap().sysclk, 80_000_000);
+
+        let invalid = [
+            // N below 8
+            PllConfig { m: 1, n: 7, r: 0 },
+            // VCO input of 16 MHz / 8 below 4 MHz
+            PllConfig { m: 7, n: 40, r: 0 },
+            // VCO output of 16 MHz * 30 above 344 MHz
+            PllConfig { m: 0, n: 30, r: 3 },
+            // VCO output of 4 MHz * 8 below 64 MHz
+            PllConfig { m: 3, n: 8, r: 0 },
+            // PLLCLK of 16 MHz * 12 / 2 = 96 MHz above 80 MHz
+            PllConfig { m: 0, n: 12, r: 0 },
+        ];
+        for &conf in &invalid {
+            let cfgr = CFGR::default().sysclk_with_pll(80.mhz(), conf);
+            assert_eq!(plan(cfgr), Err(ClockError::PllUnreachable), "{:?}", conf);
+        }
+    }
+
+    #[test]
+    fn timclk_per_prescaler() {
+        assert_eq!(timclk(80_000_000, 1), 80_000_000);
+        assert_eq!(timclk(40_000_000, 2), 80_000_000);
+        assert_eq!(timclk(5_000_000, 16), 10_000_000);
+
+        for &div in &[1u32, 2, 4, 8, 16] {
+            let pclk = 80_000_000 / div;
+            let cfgr = CFGR::default().sysclk(80.mhz()).pclk1(pclk.hz()).pclk2(pclk.hz());
+            let plan = plan(cfgr).unwrap();
+            let expected = if div == 1 { pclk } else { 2 * pclk };
+            assert_eq!(u32::from(plan.ppre1), div);
+            assert_eq!(u32::from(plan.ppre2), div);
+            assert_eq!(timclk(plan.pclk1, plan.ppre1), expected, "APB1 / {}", div);
+            assert_eq!(timclk(plan.pclk2, plan.ppre2), expected, "APB2 / {}", div);
+        }
+    }
 }
@@ -20,13 +20,8 @@ impl Rng {
         // crrcr.crrcr().modify(|_, w| w.hsi48on().set_bit()); // p. 180 in ref-manual
         // ...this is now supposed to be done in RCC configuration before freezing
 
-        // hsi48 should be turned on previously or msi at 48mhz
-        let msi = match clocks.msi() {
-            Some(msi) => msi == crate::rcc::MsiFreq::RANGE48M,
-            None => false,
-        };
-        let hsi = clocks.hsi48();
-        assert!(msi || hsi);
+        // CLK48 must run, from HSI48, MSI at 48 MHz or one of the PLLs
+        assert!(clocks.clk48().is_some());
 
         let _self = Self {
             rng: rng
@@ -1,9 +1,9 @@
 //! RTC peripheral abstraction
 
 use crate::datetime::*;
-use crate::rcc::{BDCR, APB1R1, Clocks};
+use crate::rcc::{self, BDCR, APB1R1, ClockError, Clocks, Source};
 use crate::pwr;
-use crate::stm32::{RTC};
+use crate::stm32::{RCC, RTC};
 
 /// RTC Abstraction
 pub struct Rtc {
@@ -11,9 +11,21 @@ pub struct Rtc {
 }
 
 impl Rtc {
-    pub fn rtc(rtc: RTC, apb1r1: &mut APB1R1, bdcr: &mut BDCR, pwrcr1: &mut pwr::CR1, clocks: Clocks) -> Self {
+    /// Sets up the RTC, clocked from LSE if running and LSI otherwise
+    ///
+    /// Switching from another RTC clock requires a backup domain reset, after
+    /// which LSE is restarted, along with MSI PLL-mode and LSE CSS. This fails
+    /// if LSE does not become ready in time.
+    pub fn rtc(rtc: RTC, apb1r1: &mut APB1R1, bdcr: &mut BDCR, pwrcr1: &mut pwr::CR1, clocks: Clocks) -> Result<Self, ClockError> {
+
+        // prefer the LSE crystal, LSI drifts by several percent
+        let (rtc_src_bits, prediv_s) = if clocks.lse() {
+            (0b01, 255) // 32768 Hz / (128 * 256) = 1 Hz
+        } else {
+            assert_eq!(clocks.lsi(), true); // make sure LSI is enabled
+            (0b10, 249) // 32000 Hz / (128 * 250) = 1 Hz
+        };
 
-        assert_eq!(clocks.lsi(), true); // make sure LSI is enabled
         // enable peripheral clock for communication
         apb1r1.enr().modify(|_, w| w.rtcapben().set_bit());
         pwrcr1.reg().read(); // read to allow the pwr clock to enable
@@ -21,8 +33,44 @@ impl Rtc {
         pwrcr1.reg().modify(|_, w| w.dbp().set_bit());
         while pwrcr1.reg().read().dbp().bit_is_clear() {}
         
-        bdcr.enr().modify(|_, w| { w.bdrst().set_bit() }); // reset
-        
+        // RTCSEL can only be changed once set by a backup domain reset. It is
+        // clear on a cold boot, which needs no reset.
+        let bdcr_state = bdcr.enr().read();
+        let rtcsel = bdcr_state.rtcsel().bits();
+        let lsecsson = bdcr_state.lsecsson().bit();
+        if rtcsel != 0b00 && rtcsel != rtc_src_bits {
+            // the reset also stops LSE, so restore its configuration afterwards
+            let lseon = bdcr_state.lseon().bit();
+            let lsebyp = bdcr_state.lsebyp().bit();
+            let lsedrv = bdcr_state.lsedrv().bits();
+
+            // MSI PLL-mode must be left before LSE stops
+            // NOTE(unsafe) only MSIPLLEN is touched, which `CFGR` set at freeze
+            let rcc = unsafe { &*RCC::ptr() };
+            let msipllen = rcc.cr.read().msipllen().bit();
+            rcc.cr.modify(|_, w| w.msipllen().clear_bit());
+
+            bdcr.enr().modify(|_, w| { w.bdrst().set_bit() }); // reset
+            bdcr.enr().modify(|_, w| unsafe {
+                w.bdrst()
+                    .clear_bit()
+                    .lsebyp()
+                    .bit(lsebyp)
+                    .lsedrv()
+                    .bits(lsedrv)
+            });
+            if lseon {
+                let bdcr = bdcr.enr();
+                bdcr.modify(|_, w| w.lseon().set_bit());
+                rcc::wait_for(
+                    || bdcr.read().lserdy().bit_is_set(),
+                    ClockError::OscillatorTimeout(Source::LSE),
+                    rcc::LSE_TIMEOUT,
+                )?;
+            }
+            rcc.cr.modify(|_, w| w.msipllen().bit(msipllen));
+        }
+
         bdcr.enr().modify(|_, w| unsafe {
             w.rtcsel()
                 /* 
@@ -31,12 +79,15 @@ impl Rtc {
                     10: LSI oscillator clock used as RTC clock
                     11: HSE oscillator clock divided by 32 used as RTC clock 
                 */
-                .bits(0b10)
+                .bits(rtc_src_bits)
                 .rtcen()
                 .set_bit()
-                .bdrst() // reset required for clock source change
-                .clear_bit()
         });
+        // LSE CSS is cleared by the reset, and may only be enabled once the
+        // RTC clock is selected
+        if lsecsson {
+            bdcr.enr().modify(|_, w| w.lsecsson().set_bit());
+        }
 
 
        write_protection(&rtc, false);
@@ -60,7 +111,7 @@ impl Rtc {
                 
                 rtc.prer.modify(|_, w| unsafe {
                     w.prediv_s()
-                        .bits(255)
+                        .bits(prediv_s)
                         .prediv_a()
                         .bits(127)
                 });
@@ -78,9 +129,9 @@ impl Rtc {
         }
         write_protection(&rtc, true);
 
-        Self {
+        Ok(Self {
             rtc: rtc
-        }
+        })
     }
 
     pub fn set_time(&self, time: &Time){
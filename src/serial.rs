@@ -43,6 +43,8 @@ pub enum Error {
     Overrun,
     /// Parity check error
     Parity,
+    /// Baud rate too high for the kernel clock, which must be at least 16 times faster
+    BaudRate,
     #[doc(hidden)]
     _Extensible,
 }
@@ -72,6 +74,7 @@ impl Pins<USART2> for (PD5<Alternate<AF7, Input<Floating>>>, PD6<Alternate<AF7,
 pub struct Serial<USART, PINS> {
     usart: USART,
     pins: PINS,
+    baud_rate: Bps,
 }
 
 /// Serial receiver
@@ -86,7 +89,7 @@ pub struct Tx<USART> {
 
 macro_rules! hal {
     ($(
-        $USARTX:ident: ($usartX:ident, $APB:ident, $usartXen:ident, $usartXrst:ident, $pclkX:ident, tx: ($dmacst:ident, $tx_chan:path), rx: ($dmacsr:ident, $rx_chan:path)),
+        $USARTX:ident: ($usartX:ident, $APB:ident, $usartXen:ident, $usartXrst:ident, $usartXclk:ident, tx: ($dmacst:ident, $tx_chan:path), rx: ($dmacsr:ident, $rx_chan:path)),
     )+) => {
         $(
             impl<PINS> Serial<$USARTX, PINS> {
@@ -113,18 +116,44 @@ macro_rules! hal {
                     usart.cr3.write(|w| w.dmat().set_bit().dmar().set_bit()); // enable DMA transfers
                     //usart.cr3.write(|w| w.onebit().set_bit());
 
-                    let brr = clocks.$pclkX().0 / baud_rate.0;
-                    assert!(brr >= 16, "impossible baud rate");
-                    usart.brr.write(|w| unsafe { w.bits(brr) });
+                    let serial = Serial { usart, pins, baud_rate };
+                    let brr = serial.brr(&clocks).expect("impossible baud rate");
+                    serial.usart.brr.write(|w| unsafe { w.bits(brr) });
 
                     // UE: enable USART
                     // RE: enable receiver
                     // TE: enable transceiver
-                    usart
+                    serial
+                        .usart
                         .cr1
                         .write(|w| w.ue().set_bit().re().set_bit().te().set_bit());
 
-                    Serial { usart, pins }
+                    serial
+                }
+
+                /// Re-times the baud rate after the clocks were changed by `rcc::reconfigure`
+                ///
+                /// A transmission in progress is completed first. Fails with
+                /// `Error::BaudRate` if the new kernel clock is too slow for the
+                /// baud rate, in which case the USART is left untouched.
+                pub fn reconfigure(&mut self, clocks: &Clocks) -> Result<(), Error> {
+                    let brr = self.brr(clocks)?;
+                    while self.usart.isr.read().tc().bit_is_clear() {}
+
+                    // BRR can only be written while the USART is disabled
+                    self.usart.cr1.modify(|_, w| w.ue().clear_bit());
+                    self.usart.brr.write(|w| unsafe { w.bits(brr) });
+                    self.usart.cr1.modify(|_, w| w.ue().set_bit());
+                    Ok(())
+                }
+
+                fn brr(&self, clocks: &Clocks) -> Result<u32, Error> {
+                    // the kernel clock may differ from PCLK, see `rcc::CFGR::usart1_src`
+                    let brr = clocks.$usartXclk().0 / self.baud_rate.0;
+                    if brr < 16 {
+                        return Err(Error::BaudRate);
+                    }
+                    Ok(brr)
                 }
 
                 /// Starts listening for an interrupt event
@@ -319,8 +348,8 @@ macro_rules! hal {
 }
 
 hal! {
-    USART1: (usart1, APB2, usart1en, usart1rst, pclk2, tx: (c4s, dma1::C4), rx: (c5s, dma1::C5)),
-    USART2: (usart2, APB1R1, usart2en, usart2rst, pclk1, tx: (c7s, dma1::C7), rx: (c6s, dma1::C6)),
+    USART1: (usart1, APB2, usart1en, usart1rst, usart1clk, tx: (c4s, dma1::C4), rx: (c5s, dma1::C5)),
+    USART2: (usart2, APB1R1, usart2en, usart2rst, usart2clk, tx: (c7s, dma1::C7), rx: (c6s, dma1::C6)),
 }
 
 impl<USART> fmt::Write for Tx<USART>
@@ -0,0 +1,130 @@
+//! SRAM2
+//!
+//! SRAM2 can be retained in Standby mode, has parity protection, a software
+//! triggered erase and write protection by 1 KB page. It is mapped at
+//! `SRAM2_ORIGIN`, and aliased right after SRAM1.
+//!
+//! Statics are placed in SRAM2 with the `sram2!` macro. The `.sram2` output
+//! section has to be added to `memory.x`, see the one of this crate:
+//!
+//! ```text
+//! MEMORY
+//! {
+//!   SRAM2 : ORIGIN = 0x10000000, LENGTH = 16K
+//! }
+//!
+//! SECTIONS
+//! {
+//!   .sram2 (NOLOAD) : ALIGN(4)
+//!   {
+//!     *(.sram2 .sram2.*);
+//!   } > SRAM2
+//! } INSERT AFTER .bss;
+//! ```
+
+use crate::pwr;
+use crate::rcc::APB2;
+use crate::stm32::SYSCFG;
+
+/// Start of SRAM2
+pub const SRAM2_ORIGIN: usize = 0x1000_0000;
+
+/// Size of a write protection page
+pub const PAGE_SIZE: usize = 1024;
+
+const SRAM2_KEY1: u8 = 0xCA;
+const SRAM2_KEY2: u8 = 0x53;
+
+/// Places a `static mut` in SRAM2
+///
+/// The section is not initialized at startup, so that the contents survive a
+/// reset or Standby (with retention). The initializer is only there to please
+/// the compiler, use types for which every bit pattern is valid.
+///
+/// ```ignore
+/// sram2! {
+///     static mut WAKE_COUNT: u32 = 0;
+/// }
+/// ```
+#[macro_export]
+macro_rules! sram2 {
+    ($($(#[$attr:meta])* $vis:vis static mut $name:ident: $ty:ty = $init:expr;)+) => {
+        $(
+            $(#[$attr])*
+            #[link_section = ".sram2"]
+            $vis static mut $name: $ty = $init;
+        )+
+    };
+}
+
+/// SRAM2 errors
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The page is beyond the 32 pages covered by SYSCFG_SWPR
+    InvalidPage,
+}
+
+/// Constrained SRAM2 control, through the SYSCFG peripheral
+pub struct Sram2 {
+    syscfg: SYSCFG,
+}
+
+impl Sram2 {
+    /// Enables the SYSCFG clock and takes control of SRAM2
+    pub fn new(syscfg: SYSCFG, apb2: &mut APB2) -> Self {
+        apb2.enr().modify(|_, w| w.syscfgen().set_bit());
+
+        Sram2 { syscfg }
+    }
+
+    /// Keeps SRAM2 powered in Standby mode (PWR_CR3 RRS)
+    pub fn set_standby_retention(&mut self, cr3: &mut pwr::CR3, on: bool) {
+        cr3.reg().modify(|_, w| w.rrs().bit(on));
+    }
+
+    /// Write protects the 1 KB `page` of SRAM2
+    ///
+    /// The protection can only be removed by a system reset. Pages beyond the
+    /// SRAM2 size of the device are ignored by the hardware.
+    pub fn write_protect_page(&mut self, page: u8) -> Result<(), Error> {
+        if page >= 32 {
+            return Err(Error::InvalidPage);
+        }
+        self.syscfg
+            .swpr
+            .modify(|r, w| unsafe { w.bits(r.bits() | (1 << page)) });
+        Ok(())
+    }
+
+    /// Returns whether `page` is write protected
+    pub fn is_write_protected(&self, page: u8) -> bool {
+        page < 32 && self.syscfg.swpr.read().bits() & (1 << page) != 0
+    }
+
+    /// Starts the hardware erase of SRAM2, see `is_erasing`
+    ///
+    /// Nothing located in SRAM2 (e.g. the stack) may be in use.
+    pub fn start_erase(&mut self) {
+        unsafe {
+            self.syscfg.skr.write(|w| w.key().bits(SRAM2_KEY1));
+            self.syscfg.skr.write(|w| w.key().bits(SRAM2_KEY2));
+        }
+        self.syscfg.scsr.modify(|_, w| w.sram2er().set_bit());
+    }
+
+    /// Returns whether an erase is ongoing (SRAM2BSY)
+    pub fn is_erasing(&self) -> bool {
+        self.syscfg.scsr.read().sram2bsy().bit_is_set()
+    }
+
+    /// Erases SRAM2, blocking until done
+    pub fn erase(&mut self) {
+        self.start_erase();
+        while self.is_erasing() {}
+    }
+
+    /// Releases the SYSCFG peripheral
+    pub fn free(self) -> SYSCFG {
+        self.syscfg
+    }
+}
@@ -22,8 +22,27 @@ pub enum Event {
     TimeOut,
 }
 
+/// Timer error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The timeout cannot be counted at the timer clock, being either faster
+    /// than the clock or slower than the largest prescaler allows
+    InvalidTimeout,
+}
+
+/// Prescaler (PSC) and auto-reload (ARR) values dividing `timclk` down to `frequency`
+fn psc_arr(timclk: u32, frequency: u32) -> Result<(u16, u16), Error> {
+    let ticks = timclk.checked_div(frequency).unwrap_or(0);
+    if ticks == 0 {
+        return Err(Error::InvalidTimeout);
+    }
+    let psc = u16((ticks - 1) / (1 << 16)).map_err(|_| Error::InvalidTimeout)?;
+    let arr = u16(ticks / (u32(psc) + 1)).map_err(|_| Error::InvalidTimeout)?;
+    Ok((psc, arr))
+}
+
 macro_rules! hal {
-    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident, $apb:ident),)+) => {
+    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident, $apb:ident, $timclk:ident),)+) => {
         $(
             impl Periodic for Timer<$TIM> {}
 
@@ -41,14 +60,10 @@ macro_rules! hal {
                     self.tim.cr1.modify(|_, w| w.cen().clear_bit());
 
                     self.timeout = timeout.into();
-                    let frequency = self.timeout.0;
-                    let ticks = self.clocks.pclk1().0 / frequency; // TODO check pclk that timer is on
-                    let psc = u16((ticks - 1) / (1 << 16)).unwrap();
+                    let (psc, arr) = psc_arr(self.clocks.$timclk().0, self.timeout.0)
+                        .expect("timeout out of range");
 
                     self.tim.psc.write(|w| unsafe { w.psc().bits(psc) });
-
-                    let arr = u16(ticks / u32(psc + 1)).unwrap();
-
                     self.tim.arr.write(|w| unsafe { w.bits(u32(arr)) });
 
                     // Trigger an update event to load the prescaler value to the clock
@@ -96,6 +111,19 @@ macro_rules! hal {
                     timer
                 }
 
+                /// Re-times the timer after the clocks were changed by `rcc::reconfigure`
+                ///
+                /// The count down is restarted with the current timeout. Fails with
+                /// `Error::InvalidTimeout` if the new timer clock cannot count it,
+                /// in which case the timer keeps running on its old settings.
+                pub fn set_clocks(&mut self, clocks: Clocks) -> Result<(), Error> {
+                    psc_arr(clocks.$timclk().0, self.timeout.0)?;
+                    self.clocks = clocks;
+                    let timeout = self.timeout;
+                    self.start(timeout);
+                    Ok(())
+                }
+
                 /// Starts listening for an `event`
                 pub fn listen(&mut self, event: Event) {
                     match event {
@@ -128,9 +156,9 @@ macro_rules! hal {
 }
 
 hal! {
-    TIM2: (tim2, tim2en, tim2rst, APB1R1),
-    TIM6: (tim6, tim6en, tim6rst, APB1R1),
-    TIM7: (tim7, tim7en, tim7rst, APB1R1),
-    TIM15: (tim15, tim15en, tim15rst, APB2),
-    TIM16: (tim16, tim16en, tim16rst, APB2),
+    TIM2: (tim2, tim2en, tim2rst, APB1R1, timclk1),
+    TIM6: (tim6, tim6en, tim6rst, APB1R1, timclk1),
+    TIM7: (tim7, tim7en, tim7rst, APB1R1, timclk1),
+    TIM15: (tim15, tim15en, tim15rst, APB2, timclk2),
+    TIM16: (tim16, tim16en, tim16rst, APB2, timclk2),
 }
@@ -0,0 +1,304 @@
+//! Watchdogs
+//!
+//! The independent watchdog (IWDG) runs from LSI, which it starts on its own,
+//! and keeps running when the main clock fails. The window watchdog (WWDG)
+//! runs from PCLK1 and offers an early wakeup interrupt before it resets.
+//! Once started neither can be stopped but by a reset.
+
+use crate::hal::watchdog::{Watchdog, WatchdogEnable};
+use crate::rcc::{Clocks, APB1R1};
+use crate::stm32::{DBGMCU, IWDG, WWDG};
+use crate::time::{Hertz, MilliSeconds};
+
+const LSI_HZ: u32 = 32_000;
+/// The counter is 12 bits wide
+const MAX_RELOAD: u32 = 0x0FFF;
+/// Largest prescaler selection (PR), dividing LSI by 256
+const MAX_PR: u8 = 6;
+
+const KEY_START: u16 = 0xCCCC;
+const KEY_ACCESS: u16 = 0x5555;
+const KEY_RELOAD: u16 = 0xAAAA;
+
+/// Independent watchdog (IWDG)
+pub struct IndependentWatchdog {
+    iwdg: IWDG,
+    window: Option<MilliSeconds>,
+}
+
+impl IndependentWatchdog {
+    /// Wraps the IWDG peripheral, the watchdog only runs once started
+    pub fn new(iwdg: IWDG) -> Self {
+        IndependentWatchdog { iwdg, window: None }
+    }
+
+    /// Stops the watchdog counter while the core is halted by a debugger
+    pub fn stop_on_debug(&self, dbgmcu: &DBGMCU, stop: bool) {
+        dbgmcu.apb1fzr1.modify(|_, w| w.dbg_iwdg_stop().bit(stop));
+    }
+
+    /// Sets a window, taking effect on the next `start`
+    ///
+    /// Feeding the watchdog while more than `window` remains before the
+    /// timeout resets the device as well, catching code that runs too fast.
+    pub fn set_window(&mut self, window: MilliSeconds) {
+        self.window = Some(window);
+    }
+
+    /// Returns the timeout currently programmed
+    pub fn interval(&self) -> MilliSeconds {
+        while self.is_updating() {}
+        let pr = self.iwdg.pr.read().pr().bits();
+        let rlr = u32::from(self.iwdg.rlr.read().rl().bits());
+        MilliSeconds(ticks_to_ms(pr, rlr + 1))
+    }
+
+    /// Releases the IWDG peripheral, which keeps running if started
+    pub fn free(self) -> IWDG {
+        self.iwdg
+    }
+
+    fn is_updating(&self) -> bool {
+        let sr = self.iwdg.sr.read();
+        sr.pvu().bit_is_set() || sr.rvu().bit_is_set() || sr.wvu().bit_is_set()
+    }
+
+    fn access_registers(&self) {
+        self.iwdg.kr.write(|w| unsafe { w.key().bits(KEY_ACCESS) });
+    }
+}
+
+/// Prescaler selection and reload value for `timeout`, saturating at the
+/// longest timeout of about 32.8 s
+fn timeout_to_pr_rlr(timeout: MilliSeconds) -> (u8, u16) {
+    let ticks = u64::from(timeout.0) * u64::from(LSI_HZ) / 1000;
+    let mut pr = 0;
+    // the prescaler divides LSI by 4 << PR
+    while pr < MAX_PR && ticks / (4 << pr) > u64::from(MAX_RELOAD) + 1 {
+        pr += 1;
+    }
+    let reload = (ticks / (4 << pr)).max(1).min(u64::from(MAX_RELOAD) + 1);
+    (pr, (reload - 1) as u16)
+}
+
+/// Duration of `ticks` counter periods with prescaler selection `pr`
+fn ticks_to_ms(pr: u8, ticks: u32) -> u32 {
+    ticks * (4 << pr) * 1000 / LSI_HZ
+}
+
+impl WatchdogEnable for IndependentWatchdog {
+    type Time = MilliSeconds;
+
+    fn start<T>(&mut self, period: T)
+    where
+        T: Into<MilliSeconds>,
+    {
+        let (pr, rlr) = timeout_to_pr_rlr(period.into());
+
+        // starting the watchdog also starts LSI
+        self.iwdg.kr.write(|w| unsafe { w.key().bits(KEY_START) });
+        self.access_registers();
+        self.iwdg.pr.write(|w| unsafe { w.pr().bits(pr) });
+        self.iwdg.rlr.write(|w| unsafe { w.rl().bits(rlr) });
+        while self.is_updating() {}
+
+        match self.window {
+            Some(window) => {
+                // the counter, i.e. the time left, must not exceed WINR when fed
+                let ticks = u64::from(window.0) * u64::from(LSI_HZ) / 1000 / (4 << pr);
+                let winr = ticks.min(u64::from(rlr)) as u16;
+                // writing WINR also reloads the counter
+                self.iwdg.winr.write(|w| unsafe { w.win().bits(winr) });
+                while self.is_updating() {}
+            }
+            None => self.feed(),
+        }
+    }
+}
+
+impl Watchdog for IndependentWatchdog {
+    fn feed(&mut self) {
+        self.iwdg.kr.write(|w| unsafe { w.key().bits(KEY_RELOAD) });
+    }
+}
+
+/// Interrupt events
+pub enum Event {
+    /// The window watchdog is about to reset the device (EWI)
+    EarlyWakeup,
+}
+
+/// WWDG counter value at which the reset occurs once the counter goes below
+const WWDG_T_MIN: u8 = 0x40;
+const WWDG_T_MAX: u8 = 0x7F;
+/// Largest timer base selection (WDGTB), dividing PCLK1 / 4096 by 8
+const MAX_WDGTB: u8 = 3;
+
+/// Window watchdog (WWDG)
+pub struct WindowWatchdog {
+    wwdg: WWDG,
+    pclk1: Hertz,
+    timeout: Option<MilliSeconds>,
+    window: Option<MilliSeconds>,
+    counter: u8,
+}
+
+impl WindowWatchdog {
+    /// Enables the WWDG clock, the watchdog only runs once started
+    pub fn new(wwdg: WWDG, clocks: &Clocks, apb1r1: &mut APB1R1) -> Self {
+        apb1r1.enr().modify(|_, w| w.wwdgen().set_bit());
+
+        WindowWatchdog {
+            wwdg,
+            pclk1: clocks.pclk1(),
+            timeout: None,
+            window: None,
+            counter: WWDG_T_MAX,
+        }
+    }
+
+    /// Stops the watchdog counter while the core is halted by a debugger
+    pub fn stop_on_debug(&self, dbgmcu: &DBGMCU, stop: bool) {
+        dbgmcu.apb1fzr1.modify(|_, w| w.dbg_wwdg_stop().bit(stop));
+    }
+
+    /// Sets a window, taking effect on the next `start`
+    ///
+    /// Feeding the watchdog while more than `window` remains before the
+    /// timeout resets the device as well, catching code that runs too fast.
+    pub fn set_window(&mut self, window: MilliSeconds) {
+        self.window = Some(window);
+    }
+
+    /// Starts listening for an `event`
+    ///
+    /// The interrupt can only be disabled by a reset.
+    pub fn listen(&mut self, event: Event) {
+        match event {
+            Event::EarlyWakeup => self.wwdg.cfr.modify(|_, w| w.ewi().set_bit()),
+        }
+    }
+
+    /// Returns whether the early wakeup interrupt is pending (EWIF)
+    pub fn is_early_wakeup(&self) -> bool {
+        self.wwdg.sr.read().ewif().bit_is_set()
+    }
+
+    /// Clears the early wakeup interrupt flag
+    pub fn clear_early_wakeup(&mut self) {
+        self.wwdg.sr.write(|w| w.ewif().clear_bit());
+    }
+
+    /// Re-times the watchdog after the clocks were changed by `rcc::reconfigure`
+    ///
+    /// The timer base and window are recomputed for the timeout of the last
+    /// `start` and apply right away, the new counter value on the next `feed`.
+    pub fn set_clocks(&mut self, clocks: &Clocks) {
+        self.pclk1 = clocks.pclk1();
+        if let Some(timeout) = self.timeout {
+            self.configure(timeout);
+        }
+    }
+
+    /// Releases the WWDG peripheral, which keeps running if started
+    pub fn free(self) -> WWDG {
+        self.wwdg
+    }
+
+    // programs the timer base and window for `timeout` at PCLK1
+    fn configure(&mut self, timeout: MilliSeconds) {
+        let (wdgtb, counter) = wwdg_timeout(self.pclk1.0, timeout);
+        let window = match self.window {
+            Some(window) => wwdg_window(self.pclk1.0, wdgtb, window),
+            None => WWDG_T_MAX,
+        };
+        self.timeout = Some(timeout);
+        self.counter = counter;
+
+        self.wwdg.cfr.modify(|_, w| unsafe { w.wdgtb().bits(wdgtb).w().bits(window) });
+    }
+}
+
+/// Timer base selection (WDGTB) and counter value (T) for `timeout` at
+/// `pclk1`, saturating at the longest timeout
+///
+/// The counter is decremented every 4096 * 2^WDGTB PCLK1 cycles and resets
+/// the device when going from 0x40 to 0x3F.
+fn wwdg_timeout(pclk1: u32, timeout: MilliSeconds) -> (u8, u8) {
+    let cycles = u64::from(timeout.0) * u64::from(pclk1) / 1000;
+    let mut wdgtb = 0;
+    while wdgtb < MAX_WDGTB && cycles / (4096 << wdgtb) > 64 {
+        wdgtb += 1;
+    }
+    let ticks = (cycles / (4096 << wdgtb)).max(1).min(64) as u8;
+    (wdgtb, WWDG_T_MIN + ticks - 1)
+}
+
+/// Window value (W) allowing to feed once at most `window` is left, for timer base `wdgtb`
+fn wwdg_window(pclk1: u32, wdgtb: u8, window: MilliSeconds) -> u8 {
+    let cycles = u64::from(window.0) * u64::from(pclk1) / 1000;
+    // feeding is allowed while T <= W, and the reset occurs W - 0x3F ticks later
+    let ticks = (cycles / (4096 << wdgtb)).max(1).min(64) as u8;
+    WWDG_T_MIN - 1 + ticks
+}
+
+impl WatchdogEnable for WindowWatchdog {
+    type Time = MilliSeconds;
+
+    fn start<T>(&mut self, period: T)
+    where
+        T: Into<MilliSeconds>,
+    {
+        self.configure(period.into());
+        let counter = self.counter;
+        self.wwdg.cr.write(|w| unsafe { w.wdga().set_bit().t().bits(counter) });
+    }
+}
+
+impl Watchdog for WindowWatchdog {
+    fn feed(&mut self) {
+        let counter = self.counter;
+        self.wwdg.cr.write(|w| unsafe { w.wdga().set_bit().t().bits(counter) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PCLK1: u32 = 80_000_000;
+
+    #[test]
+    fn wwdg_timeout_counter() {
+        // 80 MHz / 4096 ticks every 51.2 us
+        assert_eq!(wwdg_timeout(PCLK1, MilliSeconds(1)), (0, 0x40 + 18));
+        // 195 and 97 ticks overflow WDGTB 0 and 1
+        assert_eq!(wwdg_timeout(PCLK1, MilliSeconds(10)), (2, 0x40 + 47));
+        assert_eq!(wwdg_timeout(PCLK1, MilliSeconds(25)), (3, 0x40 + 60));
+    }
+
+    #[test]
+    fn wwdg_timeout_saturates() {
+        assert_eq!(wwdg_timeout(PCLK1, MilliSeconds(0)), (0, WWDG_T_MIN));
+        assert_eq!(wwdg_timeout(PCLK1, MilliSeconds(1000)), (MAX_WDGTB, WWDG_T_MAX));
+        assert_eq!(wwdg_timeout(4_000_000, MilliSeconds(u32::max_value())), (MAX_WDGTB, WWDG_T_MAX));
+    }
+
+    #[test]
+    fn wwdg_window_value() {
+        // 19 ticks left when feeding at T = W
+        assert_eq!(wwdg_window(PCLK1, 0, MilliSeconds(1)), 0x3F + 19);
+        assert_eq!(wwdg_window(PCLK1, 2, MilliSeconds(10)), 0x3F + 48);
+        // a window as long as the timeout allows feeding right away
+        for &ms in &[1, 2, 5, 10, 20] {
+            let (wdgtb, counter) = wwdg_timeout(PCLK1, MilliSeconds(ms));
+            assert_eq!(wwdg_window(PCLK1, wdgtb, MilliSeconds(ms)), counter, "{} ms", ms);
+        }
+    }
+
+    #[test]
+    fn wwdg_window_clamped() {
+        assert_eq!(wwdg_window(PCLK1, 0, MilliSeconds(0)), WWDG_T_MIN);
+        assert_eq!(wwdg_window(PCLK1, MAX_WDGTB, MilliSeconds(1000)), WWDG_T_MAX);
+    }
+}
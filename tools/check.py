@@ -1,6 +1,8 @@
 #! /usr/bin/env python3
 
+import glob
 import json
+import os
 import subprocess
 import sys
 
@@ -19,6 +21,43 @@ def run(mcu, cargo_cmd):
         return run_inner(cargo_cmd + ["--features={}".format(mcu)])
 
 
+def check_write_row(mcu):
+    """Checks that `write_row` in the built library neither calls nor jumps
+    out of its RAM section, in debug and release builds
+
+    Fast programming aborts as soon as code is fetched from the flash.
+    """
+    section = ".data.stm32l4xx_hal.write_row"
+    for profile in ["debug", "release"]:
+        args = ["cargo", "rustc", "--lib", "--features={},rt".format(mcu)]
+        if profile == "release":
+            args.append("--release")
+        if not run_inner(args + ["--", "--emit=obj"]):
+            return False
+
+        objects = glob.glob("target/thumbv7em-none-eabi/{}/deps/stm32l4xx_hal-*.o".format(profile))
+        obj = max(objects, key=os.path.getmtime)
+        relocations = subprocess.check_output(
+            ["arm-none-eabi-objdump", "-r", "-j", section, obj],
+            universal_newlines=True)
+        disassembly = subprocess.check_output(
+            ["arm-none-eabi-objdump", "-d", "-j", section, obj],
+            universal_newlines=True)
+        print(disassembly)
+
+        # instruction lines read "address:\tencoding\tmnemonic\toperands"
+        instructions = [line.split("\t") for line in disassembly.splitlines()]
+        calls = ["\t".join(fields) for fields in instructions
+                 if len(fields) > 2 and (fields[2].strip() in ("bl", "blx")
+                     or (fields[2].strip() == "bx" and fields[3].strip() != "lr"))]
+        if "R_ARM" in relocations or calls:
+            print("`write_row` leaves RAM in the {} build:".format(profile))
+            print(relocations)
+            print("\n".join(calls))
+            return False
+    return True
+
+
 def main():
     cargo_meta = json.loads(
         subprocess.check_output("cargo metadata --no-deps --format-version=1",
@@ -32,6 +71,11 @@ def main():
             for x in crate_info["features"].keys()
             if x != "rt"]
 
+    if 'write_row' in sys.argv:
+        if not check_write_row("stm32l4x2"):
+            sys.exit(-1)
+        return
+
     if 'size_check' in sys.argv:
         cargo_cmd = ['cargo', 'build', '--release']
     else: